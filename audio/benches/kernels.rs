@@ -0,0 +1,104 @@
+//! Benchmarks for the block processing kernels.
+//!
+//! Run with `cargo bench -p servo-media-audio`. Each kernel is measured
+//! against its scalar fallback over one render quantum of stereo audio.
+
+#![feature(test)]
+
+extern crate servo_media_audio;
+extern crate test;
+
+use servo_media_audio::block::{Block, FRAMES_PER_BLOCK_USIZE};
+use servo_media_audio::kernels::{self, scalar};
+use servo_media_audio::node::ChannelInterpretation;
+use test::{black_box, Bencher};
+
+const LEN: usize = 2 * FRAMES_PER_BLOCK_USIZE;
+
+fn data(len: usize) -> Vec<f32> {
+    (0..len).map(|i| (i as f32 * 0.01).sin()).collect()
+}
+
+fn stereo_block() -> Block {
+    let data = data(FRAMES_PER_BLOCK_USIZE);
+    let mut block = Block::empty(FRAMES_PER_BLOCK_USIZE);
+    block.push_chan(&data);
+    block.push_chan(&data);
+    block
+}
+
+#[bench]
+fn add(b: &mut Bencher) {
+    let (mut dst, src) = (data(LEN), data(LEN));
+    b.iter(|| kernels::add(black_box(&mut dst), black_box(&src)));
+}
+
+#[bench]
+fn add_scalar(b: &mut Bencher) {
+    let (mut dst, src) = (data(LEN), data(LEN));
+    b.iter(|| scalar::add(black_box(&mut dst), black_box(&src)));
+}
+
+#[bench]
+fn scale(b: &mut Bencher) {
+    let mut dst = data(LEN);
+    b.iter(|| kernels::scale(black_box(&mut dst), black_box(1.)));
+}
+
+#[bench]
+fn scale_scalar(b: &mut Bencher) {
+    let mut dst = data(LEN);
+    b.iter(|| scalar::scale(black_box(&mut dst), black_box(1.)));
+}
+
+#[bench]
+fn mul(b: &mut Bencher) {
+    let (mut dst, gains) = (data(LEN), data(LEN));
+    b.iter(|| kernels::mul(black_box(&mut dst), black_box(&gains)));
+}
+
+#[bench]
+fn mul_scalar(b: &mut Bencher) {
+    let (mut dst, gains) = (data(LEN), data(LEN));
+    b.iter(|| scalar::mul(black_box(&mut dst), black_box(&gains)));
+}
+
+#[bench]
+fn add_scaled(b: &mut Bencher) {
+    let (mut dst, src) = (data(LEN), data(LEN));
+    b.iter(|| kernels::add_scaled(black_box(&mut dst), black_box(&src), black_box(0.5)));
+}
+
+#[bench]
+fn add_scaled_scalar(b: &mut Bencher) {
+    let (mut dst, src) = (data(LEN), data(LEN));
+    b.iter(|| scalar::add_scaled(black_box(&mut dst), black_box(&src), black_box(0.5)));
+}
+
+#[bench]
+fn interleave(b: &mut Bencher) {
+    let (mut dst, src) = (data(LEN), data(LEN));
+    b.iter(|| kernels::interleave(black_box(&mut dst), black_box(&src), 2, FRAMES_PER_BLOCK_USIZE));
+}
+
+#[bench]
+fn interleave_scalar(b: &mut Bencher) {
+    let (mut dst, src) = (data(LEN), data(LEN));
+    b.iter(|| scalar::interleave(black_box(&mut dst), black_box(&src), 2, FRAMES_PER_BLOCK_USIZE));
+}
+
+#[bench]
+fn block_sum(b: &mut Bencher) {
+    let (block, other) = (stereo_block(), stereo_block());
+    b.iter(|| black_box(block.clone()).sum(black_box(other.clone())));
+}
+
+#[bench]
+fn block_downmix_stereo_to_mono(b: &mut Bencher) {
+    let block = stereo_block();
+    b.iter(|| {
+        let mut block = black_box(block.clone());
+        block.mix(1, ChannelInterpretation::Speakers);
+        block
+    });
+}
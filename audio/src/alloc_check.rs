@@ -0,0 +1,129 @@
+//! Debug mode which checks that rendering does not allocate.
+//!
+//! Enabling the `alloc_check` feature installs a global allocator that
+//! counts the allocations made while rendering a block, on the calling
+//! thread and on the workers helping it. `AudioGraph::process` then asserts
+//! that, once the graph has settled, no allocations are made at all.
+//!
+//! The block pool and the scratch space are allowed to grow to the working
+//! set of the graph while it warms up, during the first `WARM_UP_QUANTA`
+//! render quanta after it changes. A node whose output only starts later,
+//! like a source scheduled to start after that, can still make the pool
+//! grow afterwards; processing such a graph can be wrapped in `warm_up` to
+//! allow it.
+//!
+//! As this replaces the global allocator it is only meant to be
+//! used for debugging and testing.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+thread_local!(static COUNTING: Cell<bool> = Cell::new(false));
+thread_local!(static ALLOCATIONS: Cell<usize> = Cell::new(0));
+thread_local!(static WARMING_UP: Cell<bool> = Cell::new(false));
+
+/// Number of render quanta after the graph changes during which the
+/// pool may grow. Blocks cached on connections are only given back to the
+/// pool once the next block replaces them, so this takes more than one.
+pub const WARM_UP_QUANTA: u32 = 2;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count() {
+    let _ = COUNTING.try_with(|counting| {
+        if counting.get() {
+            let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        }
+    });
+}
+
+fn set_counting(counting: bool) -> bool {
+    COUNTING
+        .try_with(|c| c.replace(counting))
+        .unwrap_or(false)
+}
+
+/// Run `f`, returning its result along with the number of
+/// allocations it made on this thread
+pub fn count_allocations<R, F: FnOnce() -> R>(f: F) -> (R, usize) {
+    let before = ALLOCATIONS.with(|a| a.get());
+    let was_counting = set_counting(true);
+    let result = f();
+    set_counting(was_counting);
+    let after = ALLOCATIONS.with(|a| a.get());
+    (result, after - before)
+}
+
+/// Whether the allocations of this thread are being counted
+pub fn is_counting() -> bool {
+    COUNTING.try_with(|c| c.get()).unwrap_or(false)
+}
+
+/// Count `allocations` made on another thread on behalf of this one
+pub fn record(allocations: usize) {
+    if is_counting() {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + allocations));
+    }
+}
+
+/// Run `f` without counting any of its allocations
+pub fn allow<R, F: FnOnce() -> R>(f: F) -> R {
+    let was_counting = set_counting(false);
+    let result = f();
+    set_counting(was_counting);
+    result
+}
+
+/// Run `f`, letting the pool and scratch space grow without
+/// counting it as allocating
+pub fn warm_up<R, F: FnOnce() -> R>(f: F) -> R {
+    let was_warming_up = set_warming_up(true);
+    let result = f();
+    set_warming_up(was_warming_up);
+    result
+}
+
+/// Whether the pool and scratch space may grow on this thread
+pub fn is_warming_up() -> bool {
+    WARMING_UP.try_with(|w| w.get()).unwrap_or(false)
+}
+
+fn set_warming_up(warming_up: bool) -> bool {
+    WARMING_UP
+        .try_with(|w| w.replace(warming_up))
+        .unwrap_or(false)
+}
+
+/// Run `f`, not counting its allocations if the pool or
+/// scratch space is allowed to grow
+pub(crate) fn grow<R, F: FnOnce() -> R>(f: F) -> R {
+    if is_warming_up() {
+        allow(f)
+    } else {
+        f()
+    }
+}
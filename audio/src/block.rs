@@ -1,16 +1,22 @@
 use byte_slice_cast::*;
+use channel_layout::MixingMatrix;
 use graph::{PortIndex, PortKind};
+use kernels;
 use node::ChannelInterpretation;
-use smallvec::SmallVec;
-use std::f32::consts::SQRT_2;
+use pool;
+use std::f32::consts::FRAC_1_SQRT_2;
 use std::mem;
 use std::ops::*;
 
 // defined by spec
 // https://webaudio.github.io/web-audio-api/#render-quantum
+/// The default size of a render quantum
 pub const FRAMES_PER_BLOCK: Tick = Tick(128);
 pub const FRAMES_PER_BLOCK_USIZE: usize = FRAMES_PER_BLOCK.0 as usize;
 
+/// The largest render quantum a context can ask for
+pub const MAX_FRAMES_PER_BLOCK: usize = 16384;
+
 /// A tick, i.e. the time taken for a single frame
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
 pub struct Tick(pub u64);
@@ -23,28 +29,39 @@ pub struct Tick(pub u64);
 /// Some nodes have multiple inputs or outputs, which is
 /// where this becomes useful. Source nodes have an input
 /// of an empty chunk.
+///
+/// The storage for the blocks is recycled through the block pool.
 pub struct Chunk {
-    pub blocks: SmallVec<[Block; 1]>,
+    pub blocks: pool::ChunkStorage,
 }
 
 impl Default for Chunk {
     fn default() -> Self {
         Chunk {
-            blocks: SmallVec::new(),
+            blocks: pool::take_chunk(),
         }
     }
 }
 
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        pool::recycle_chunk(mem::replace(&mut self.blocks, Default::default()));
+    }
+}
+
 impl Chunk {
     pub fn len(&self) -> usize {
         self.blocks.len()
     }
 }
 
-/// We render audio in blocks of size FRAMES_PER_BLOCK
+/// We render audio in blocks of one render quantum, whose size is set
+/// by the context and carried by each block
 ///
 /// A single block may contain multiple channels
-#[derive(Clone)]
+///
+/// Buffers are obtained from and given back to the block pool,
+/// so that rendering does not need to allocate.
 pub struct Block {
     /// The number of channels in this block
     channels: u8,
@@ -52,36 +69,80 @@ pub struct Block {
     /// same content at once. Happens when audio is upmixed or when a source like
     /// an oscillator node has multiple channel outputs
     repeat: bool,
+    /// The number of frames of each channel
+    frames: usize,
     /// If this vector is empty, it is a shorthand for "silence"
     /// It is possible to obtain an explicitly silent buffer via .explicit_silence()
     ///
-    /// This must be of length channels * FRAMES_PER_BLOCK, unless `repeat` is true,
-    /// in which case it will be of length FRAMES_PER_BLOCK
+    /// This must be of length channels * frames, unless `repeat` is true,
+    /// in which case it will be of length frames
     buffer: Vec<f32>,
 }
 
-impl Default for Block {
-    fn default() -> Self {
+impl Clone for Block {
+    fn clone(&self) -> Self {
+        let mut buffer = Vec::new();
+        if !self.is_silence() {
+            buffer = pool::take_buffer(self.buffer.len() / self.frames, self.frames);
+            buffer.extend_from_slice(&self.buffer);
+        }
+        Block {
+            channels: self.channels,
+            repeat: self.repeat,
+            frames: self.frames,
+            buffer,
+        }
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        pool::recycle_buffer(mem::replace(&mut self.buffer, Vec::new()), self.frames);
+    }
+}
+
+impl Block {
+    /// Silent single-channel block of `frames` frames
+    pub fn new(frames: usize) -> Self {
+        debug_assert!(frames > 0 && frames <= MAX_FRAMES_PER_BLOCK);
         Block {
             channels: 1,
             repeat: false,
+            frames,
             buffer: Vec::new(),
         }
     }
-}
 
-impl Block {
     /// Empty block with no channels, for pushing
-    /// new channels to.
+    /// new channels of `frames` frames to.
     ///
     /// Must be used with push_chan
-    pub fn empty() -> Self {
-        Block {
-            channels: 0,
-            ..Default::default()
+    pub fn empty(frames: usize) -> Self {
+        let mut block = Block::new(frames);
+        block.channels = 0;
+        block
+    }
+
+    /// The number of frames of each channel
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Make sure the buffer can hold the given number of channels
+    /// without reallocating, keeping its contents
+    fn reserve_chans(&mut self, channels: u8) {
+        if self.buffer.capacity() < channels as usize * self.frames {
+            let mut new = pool::take_buffer(channels as usize, self.frames);
+            new.extend_from_slice(&self.buffer);
+            self.replace_buffer(new);
         }
     }
 
+    /// Swap in a new buffer, giving the old one back to the pool
+    fn replace_buffer(&mut self, buffer: Vec<f32>) {
+        pool::recycle_buffer(mem::replace(&mut self.buffer, buffer), self.frames);
+    }
+
     /// This provides the entire buffer as a mutable slice of u8
     pub fn as_mut_byte_slice(&mut self) -> &mut [u8] {
         self.data_mut().as_mut_byte_slice().expect("casting failed")
@@ -95,14 +156,13 @@ impl Block {
             other
         } else {
             debug_assert!(self.channels == other.channels);
+            debug_assert!(self.frames == other.frames);
             if self.repeat ^ other.repeat {
                 self.explicit_repeat();
                 other.explicit_repeat();
             }
             debug_assert!(self.buffer.len() == other.buffer.len());
-            for (a, b) in self.buffer.iter_mut().zip(other.buffer.iter()) {
-                *a += b
-            }
+            kernels::add(&mut self.buffer, &other.buffer);
             self
         }
     }
@@ -110,7 +170,8 @@ impl Block {
     /// If this is in "silence" mode without a buffer, allocate a silent buffer
     pub fn explicit_silence(&mut self) {
         if self.buffer.is_empty() {
-            self.buffer.resize(FRAMES_PER_BLOCK_USIZE, 0.);
+            self.reserve_chans(1);
+            self.buffer.resize(self.frames, 0.);
             self.repeat = true;
         }
     }
@@ -123,23 +184,25 @@ impl Block {
 
     pub fn explicit_repeat(&mut self) {
         if self.repeat && self.channels > 1 {
-            let mut new = Vec::with_capacity(FRAMES_PER_BLOCK_USIZE * self.channels as usize);
+            let mut new = pool::take_buffer(self.channels as usize, self.frames);
             for _ in 0..self.channels {
                 new.extend(&self.buffer)
             }
 
-            self.buffer = new;
+            self.replace_buffer(new);
             self.repeat = false;
         } else if self.is_silence() {
+            let channels = self.channels;
+            self.reserve_chans(channels);
             self.buffer
-                .resize(FRAMES_PER_BLOCK_USIZE * self.channels as usize, 0.);
+                .resize(self.frames * self.channels as usize, 0.);
         }
     }
 
     pub fn data_chan_mut(&mut self, chan: u8) -> &mut [f32] {
         self.explicit_repeat();
-        let start = chan as usize * FRAMES_PER_BLOCK_USIZE;
-        &mut self.buffer[start..start + FRAMES_PER_BLOCK_USIZE]
+        let start = chan as usize * self.frames;
+        &mut self.buffer[start..start + self.frames]
     }
 
     #[inline]
@@ -147,13 +210,13 @@ impl Block {
         let offset = if self.repeat {
             0
         } else {
-            chan as usize * FRAMES_PER_BLOCK_USIZE
+            chan as usize * self.frames
         };
-        &self.buffer[offset..offset + FRAMES_PER_BLOCK_USIZE]
+        &self.buffer[offset..offset + self.frames]
     }
 
     pub fn take(&mut self) -> Block {
-        let mut new = Block::default();
+        let mut new = Block::new(self.frames);
         new.channels = self.channels;
         mem::replace(self, new)
     }
@@ -181,7 +244,9 @@ impl Block {
     pub fn push_chan(&mut self, data: &[f32]) {
         assert!(!self.repeat);
         assert!(!self.is_silence() || self.channels == 0);
-        assert!(data.len() == FRAMES_PER_BLOCK_USIZE);
+        assert!(data.len() == self.frames);
+        let channels = self.channels + 1;
+        self.reserve_chans(channels);
         self.buffer.extend(data);
         self.channels += 1;
     }
@@ -224,6 +289,11 @@ impl Block {
             // - Stereo: [L, R]
             // - Quad: [L, R, SL, SR]
             // - 5.1: [L, R, C, LFE, SL, SR]
+            // - 7.1: [L, R, C, LFE, SL, SR, BL, BR]
+            //
+            // 7.1 is not covered by the spec. It is mixed to and from
+            // the other layouts by way of 5.1, with the back channels
+            // folded into the side channels.
 
             match (self.channels, channels) {
                 // Upmixing
@@ -241,21 +311,28 @@ impl Block {
                     self.resize_silence(4);
                 }
                 (1, 6) => {
-                    let mut v = Vec::with_capacity(channels as usize * FRAMES_PER_BLOCK_USIZE);
+                    let mut v = pool::take_buffer(channels as usize, self.frames);
                     // output.{L, R} = 0
-                    v.resize(2 * FRAMES_PER_BLOCK_USIZE, 0.);
+                    v.resize(2 * self.frames, 0.);
                     // output.C = input
                     v.extend(&self.buffer);
-                    self.buffer = v;
+                    self.replace_buffer(v);
                     // output.{LFE, SL, SR} = 0
                     self.resize_silence(6);
                 }
 
+                (1, 8) | (4, 8) => {
+                    self.mix(6, interpretation);
+                    // output.{BL, BR} = 0
+                    self.resize_silence(8);
+                }
+
                 // stereo
-                (2, 4) | (2, 6) => {
+                (2, 4) | (2, 6) | (2, 8) => {
                     // output.{L, R} = input.{L, R}
-                    // (5.1) output.{C, LFE} = 0
+                    // (5.1, 7.1) output.{C, LFE} = 0
                     // output.{SL, SR} = 0
+                    // (7.1) output.{BL, BR} = 0
                     self.resize_silence(channels);
                 }
 
@@ -266,124 +343,153 @@ impl Block {
                     // a `repeat` quad block should be rare
                     self.explicit_repeat();
 
-                    let mut v = Vec::with_capacity(6 * FRAMES_PER_BLOCK_USIZE);
+                    let mut v = pool::take_buffer(6, self.frames);
                     // output.{L, R} = input.{L, R}
-                    v.extend(&self.buffer[0..2 * FRAMES_PER_BLOCK_USIZE]);
+                    v.extend(&self.buffer[0..2 * self.frames]);
                     // output.{C, LFE} = 0
-                    v.resize(4 * FRAMES_PER_BLOCK_USIZE, 0.);
+                    v.resize(4 * self.frames, 0.);
                     // output.{SL, R} = input.{SL, SR}
-                    v.extend(&self.buffer[2 * FRAMES_PER_BLOCK_USIZE..]);
-                    self.buffer = v;
+                    v.extend(&self.buffer[2 * self.frames..]);
+                    self.replace_buffer(v);
                     self.channels = channels;
                 }
 
+                // 5.1
+                (6, 8) => {
+                    // output.{L, R, C, LFE, SL, SR} = input
+                    // output.{BL, BR} = 0
+                    self.resize_silence(8);
+                }
+
                 // Downmixing
                 // https://webaudio.github.io/web-audio-api/#down-mix
 
                 // mono
                 (2, 1) => {
-                    let mut v = Vec::with_capacity(FRAMES_PER_BLOCK_USIZE);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
-                        // output = 0.5 * (input.L + input.R);
-                        let o =
-                            0.5 * (self.data_chan_frame(frame, 0) + self.data_chan_frame(frame, 1));
-                        v.push(o);
-                    }
-                    self.buffer = v;
+                    let mut v = pool::take_buffer(1, self.frames);
+                    // output = 0.5 * (input.L + input.R);
+                    v.extend_from_slice(self.data_chan(0));
+                    kernels::add(&mut v, self.data_chan(1));
+                    kernels::scale(&mut v, 0.5);
+                    self.replace_buffer(v);
                     self.channels = 1;
                     self.repeat = false;
                 }
                 (4, 1) => {
-                    let mut v = Vec::with_capacity(FRAMES_PER_BLOCK_USIZE);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
-                        // output = 0.5 * (input.L + input.R + input.SL + input.SR);
-                        let o = 0.25 * (self.data_chan_frame(frame, 0)
-                            + self.data_chan_frame(frame, 1)
-                            + self.data_chan_frame(frame, 2)
-                            + self.data_chan_frame(frame, 3));
-                        v.push(o);
+                    let mut v = pool::take_buffer(1, self.frames);
+                    // output = 0.25 * (input.L + input.R + input.SL + input.SR);
+                    v.extend_from_slice(self.data_chan(0));
+                    for chan in 1..4 {
+                        kernels::add(&mut v, self.data_chan(chan));
                     }
-                    self.buffer = v;
+                    kernels::scale(&mut v, 0.25);
+                    self.replace_buffer(v);
                     self.channels = 1;
                     self.repeat = false;
                 }
                 (6, 1) => {
-                    let mut v = Vec::with_capacity(FRAMES_PER_BLOCK_USIZE);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
-                        // output = sqrt(0.5) * (input.L + input.R) + input.C + 0.5 * (input.SL + input.SR)
-                        let o =
-                            // sqrt(0.5) * (input.L + input.R)
-                            SQRT_2 * (self.data_chan_frame(frame, 0) +
-                                      self.data_chan_frame(frame, 1)) +
-                            // input.C
-                            self.data_chan_frame(frame, 2) +
-                            // (ignore LFE)
-                            // + 0 * self.buffer[frame + 3 * FRAMES_PER_BLOCK_USIZE]
-                            // 0.5 * (input.SL + input.SR)
-                            0.5 * (self.data_chan_frame(frame, 4) +
-                                   self.data_chan_frame(frame, 5));
-                        v.push(o);
-                    }
-                    self.buffer = v;
+                    let mut v = pool::take_buffer(1, self.frames);
+                    // output = sqrt(0.5) * (input.L + input.R) + input.C + 0.5 * (input.SL + input.SR)
+
+                    // sqrt(0.5) * (input.L + input.R)
+                    v.extend_from_slice(self.data_chan(0));
+                    kernels::add(&mut v, self.data_chan(1));
+                    kernels::scale(&mut v, FRAC_1_SQRT_2);
+                    // input.C
+                    kernels::add(&mut v, self.data_chan(2));
+                    // (ignore LFE)
+                    // 0.5 * (input.SL + input.SR)
+                    kernels::add_scaled(&mut v, self.data_chan(4), 0.5);
+                    kernels::add_scaled(&mut v, self.data_chan(5), 0.5);
+                    self.replace_buffer(v);
                     self.channels = 1;
                     self.repeat = false;
                 }
 
                 // stereo
                 (4, 2) => {
-                    let mut v = Vec::with_capacity(2 * FRAMES_PER_BLOCK_USIZE);
-                    v.resize(2 * FRAMES_PER_BLOCK_USIZE, 0.);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
+                    let mut v = pool::take_buffer(2, self.frames);
+                    v.extend_from_slice(self.data_chan(0));
+                    v.extend_from_slice(self.data_chan(1));
+                    {
+                        let (l, r) = v.split_at_mut(self.frames);
                         // output.L = 0.5 * (input.L + input.SL)
-                        v[frame] =
-                            0.5 * (self.data_chan_frame(frame, 0) + self.data_chan_frame(frame, 2));
+                        kernels::add(l, self.data_chan(2));
+                        kernels::scale(l, 0.5);
                         // output.R = 0.5 * (input.R + input.SR)
-                        v[frame + FRAMES_PER_BLOCK_USIZE] =
-                            0.5 * (self.data_chan_frame(frame, 1) + self.data_chan_frame(frame, 3));
+                        kernels::add(r, self.data_chan(3));
+                        kernels::scale(r, 0.5);
                     }
-                    self.buffer = v;
+                    self.replace_buffer(v);
                     self.channels = 2;
                     self.repeat = false;
                 }
                 (6, 2) => {
-                    let mut v = Vec::with_capacity(2 * FRAMES_PER_BLOCK_USIZE);
-                    v.resize(2 * FRAMES_PER_BLOCK_USIZE, 0.);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
+                    let mut v = pool::take_buffer(2, self.frames);
+                    v.extend_from_slice(self.data_chan(0));
+                    v.extend_from_slice(self.data_chan(1));
+                    {
+                        let (l, r) = v.split_at_mut(self.frames);
                         // output.L = L + sqrt(0.5) * (input.C + input.SL)
-                        v[frame] = self.data_chan_frame(frame, 0)
-                            + SQRT_2
-                                * (self.data_chan_frame(frame, 2) + self.data_chan_frame(frame, 4));
+                        kernels::add_scaled(l, self.data_chan(2), FRAC_1_SQRT_2);
+                        kernels::add_scaled(l, self.data_chan(4), FRAC_1_SQRT_2);
                         // output.R = R + sqrt(0.5) * (input.C + input.SR)
-                        v[frame + FRAMES_PER_BLOCK_USIZE] = self.data_chan_frame(frame, 1)
-                            + SQRT_2
-                                * (self.data_chan_frame(frame, 2) + self.data_chan_frame(frame, 5));
+                        kernels::add_scaled(r, self.data_chan(2), FRAC_1_SQRT_2);
+                        kernels::add_scaled(r, self.data_chan(5), FRAC_1_SQRT_2);
                     }
-                    self.buffer = v;
+                    self.replace_buffer(v);
                     self.channels = 2;
                     self.repeat = false;
                 }
 
                 // quad
                 (6, 4) => {
-                    let mut v = Vec::with_capacity(6 * FRAMES_PER_BLOCK_USIZE);
-                    v.resize(6 * FRAMES_PER_BLOCK_USIZE, 0.);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
+                    let mut v = pool::take_buffer(4, self.frames);
+                    v.extend_from_slice(self.data_chan(0));
+                    v.extend_from_slice(self.data_chan(1));
+                    // output.SL = input.SL
+                    v.extend_from_slice(self.data_chan(4));
+                    // output.SR = input.SR
+                    v.extend_from_slice(self.data_chan(5));
+                    {
+                        let (l, rest) = v.split_at_mut(self.frames);
+                        let r = &mut rest[..self.frames];
                         // output.L = L + sqrt(0.5) * input.C
-                        v[frame] = self.data_chan_frame(frame, 0)
-                            + SQRT_2 * self.data_chan_frame(frame, 2);
+                        kernels::add_scaled(l, self.data_chan(2), FRAC_1_SQRT_2);
                         // output.R = R + sqrt(0.5) * input.C
-                        v[frame + FRAMES_PER_BLOCK_USIZE] = self.data_chan_frame(frame, 1)
-                            + SQRT_2 * self.data_chan_frame(frame, 2);
-                        // output.SL = input.SL
-                        v[frame + 2 * FRAMES_PER_BLOCK_USIZE] = self.data_chan_frame(frame, 4);
-                        // output.SR = input.SR
-                        v[frame + 3 * FRAMES_PER_BLOCK_USIZE] = self.data_chan_frame(frame, 5);
+                        kernels::add_scaled(r, self.data_chan(2), FRAC_1_SQRT_2);
                     }
-                    self.buffer = v;
+                    self.replace_buffer(v);
                     self.channels = 4;
                     self.repeat = false;
                 }
 
+                // 7.1
+                (8, 1) | (8, 2) | (8, 4) => {
+                    self.mix(6, interpretation);
+                    self.mix(channels, interpretation);
+                }
+                (8, 6) => {
+                    let mut v = pool::take_buffer(6, self.frames);
+                    // output.{L, R, C, LFE, SL, SR} = input.{L, R, C, LFE, SL, SR}
+                    for chan in 0..6 {
+                        v.extend_from_slice(self.data_chan(chan));
+                    }
+                    {
+                        let (sl, sr) = v[4 * self.frames..]
+                            .split_at_mut(self.frames);
+                        // output.SL = sqrt(0.5) * (input.SL + input.BL)
+                        kernels::add(sl, self.data_chan(6));
+                        kernels::scale(sl, FRAC_1_SQRT_2);
+                        // output.SR = sqrt(0.5) * (input.SR + input.BR)
+                        kernels::add(sr, self.data_chan(7));
+                        kernels::scale(sr, FRAC_1_SQRT_2);
+                    }
+                    self.replace_buffer(v);
+                    self.channels = 6;
+                    self.repeat = false;
+                }
+
                 // If it's not a known kind of speaker configuration, treat as
                 // discrete
                 _ => {
@@ -394,11 +500,37 @@ impl Block {
         }
     }
 
+    /// Mix the channels through a matrix. The block must
+    /// have as many channels as the matrix has inputs.
+    pub fn mix_matrix(&mut self, matrix: &MixingMatrix) {
+        debug_assert!(self.channels == matrix.inputs());
+        let outputs = matrix.outputs();
+        if self.is_silence() {
+            self.channels = outputs;
+            return;
+        }
+
+        let frames = self.frames;
+        let mut v = pool::take_buffer(outputs as usize, self.frames);
+        v.resize(outputs as usize * frames, 0.);
+        for (output, out) in v.chunks_mut(frames).enumerate() {
+            for (input, &gain) in matrix.row(output as u8).iter().enumerate() {
+                if gain != 0. {
+                    kernels::add_scaled(out, self.data_chan(input as u8), gain);
+                }
+            }
+        }
+        self.replace_buffer(v);
+        self.channels = outputs;
+        self.repeat = false;
+    }
+
     /// Resize to add or remove channels, fill extra channels with silence
     fn resize_silence(&mut self, channels: u8) {
         self.explicit_repeat();
+        self.reserve_chans(channels);
         self.buffer
-            .resize(FRAMES_PER_BLOCK_USIZE * channels as usize, 0.);
+            .resize(self.frames * channels as usize, 0.);
         self.channels = channels;
     }
 
@@ -413,17 +545,39 @@ impl Block {
     }
 
     pub fn interleave(&mut self) -> Vec<f32> {
+        let mut vec = Vec::new();
+        vec.resize(self.channels as usize * self.frames, 0.);
+        self.interleave_into(&mut vec);
+        vec
+    }
+
+    /// Interleave the channels of this block into a caller provided
+    /// buffer of length channels * frames
+    pub fn interleave_into(&mut self, out: &mut [f32]) {
         self.explicit_repeat();
-        let mut vec = Vec::with_capacity(self.buffer.len());
-        // FIXME this isn't too efficient
-        vec.resize(self.buffer.len(), 0.);
-        for frame in 0..FRAMES_PER_BLOCK_USIZE {
-            let channels = self.channels as usize;
-            for chan in 0..channels {
-                vec[frame * channels + chan] = self.buffer[chan * FRAMES_PER_BLOCK_USIZE + frame]
-            }
+        kernels::interleave(
+            out,
+            &self.buffer,
+            self.channels as usize,
+            self.frames,
+        );
+    }
+
+    /// Apply a constant gain to all channels
+    pub fn scale(&mut self, gain: f32) {
+        // a repeated block stores its channel once, so this
+        // scales every channel
+        kernels::scale(&mut self.buffer, gain);
+    }
+
+    /// Apply a per-frame gain to all channels
+    ///
+    /// `gains` must have one value for each frame of the block
+    pub fn scale_by(&mut self, gains: &[f32]) {
+        debug_assert!(gains.len() == self.frames);
+        for chan in self.buffer.chunks_mut(self.frames) {
+            kernels::mul(chan, gains);
         }
-        vec
     }
 
     pub fn is_empty(&self) -> bool {
@@ -434,6 +588,7 @@ impl Block {
 /// An iterator over frames in a block
 pub struct FrameIterator<'a> {
     frame: Tick,
+    end: Tick,
     block: &'a mut Block,
 }
 
@@ -442,6 +597,7 @@ impl<'a> FrameIterator<'a> {
     pub fn new(block: &'a mut Block) -> Self {
         FrameIterator {
             frame: Tick(0),
+            end: Tick(block.frames as u64),
             block,
         }
     }
@@ -454,7 +610,7 @@ impl<'a> FrameIterator<'a> {
     #[inline]
     pub fn next<'b>(&'b mut self) -> Option<FrameRef<'b>> {
         let curr = self.frame;
-        if curr < FRAMES_PER_BLOCK {
+        if curr < self.end {
             self.frame.advance();
             Some(FrameRef {
                 frame: curr,
@@ -499,7 +655,7 @@ impl<'a> FrameRef<'a> {
         } else {
             for chan in 0..self.block.channels {
                 f(&mut self.block.buffer
-                    [chan as usize * FRAMES_PER_BLOCK_USIZE + self.frame.0 as usize])
+                    [chan as usize * self.block.frames + self.frame.0 as usize])
             }
         }
     }
@@ -1,6 +1,7 @@
-use block::{Block, Chunk, Tick, FRAMES_PER_BLOCK};
+use block::{Block, Chunk, Tick};
+use error::{AudioError, AudioResult};
 use node::{AudioNodeEngine, AudioScheduledSourceNodeMessage, BlockInfo, OnEndedCallback};
-use node::{AudioNodeType, ChannelInfo};
+use node::{AudioNodeInit, AudioNodeType, ChannelInfo};
 use param::{Param, ParamType};
 
 /// Control messages directed to AudioBufferSourceNodes.
@@ -11,7 +12,7 @@ pub enum AudioBufferSourceNodeMessage {
 }
 
 /// This specifies options for constructing an AudioBufferSourceNode.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioBufferSourceNodeOptions {
     /// The audio asset to be played.
     pub buffer: Option<AudioBuffer>,
@@ -104,15 +105,37 @@ impl AudioNodeEngine for AudioBufferSourceNode {
         AudioNodeType::AudioBufferSourceNode
     }
 
+    fn init_options(&self) -> AudioNodeInit {
+        AudioNodeInit::AudioBufferSourceNode(AudioBufferSourceNodeOptions {
+            buffer: self.buffer.clone(),
+            detune: self.detune.value(),
+            loop_enabled: self.loop_enabled,
+            loop_end: self.loop_end,
+            loop_start: self.loop_start,
+            playback_rate: self.playback_rate.value(),
+        })
+    }
+
     fn input_count(&self) -> u32 {
         0
     }
 
+    fn is_active(&self, info: &BlockInfo) -> bool {
+        match self.buffer {
+            // Nothing left to play, but the ended event may still need firing
+            Some(ref buffer) if self.playback_offset >= buffer.len() => {
+                self.start_at.is_some() && self.onended_callback.is_some()
+            }
+            Some(_) => self.should_process_at(info),
+            None => false,
+        }
+    }
+
     fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 0);
 
         if self.buffer.is_none() {
-            inputs.blocks.push(Default::default());
+            inputs.blocks.push(Block::new(info.frames));
             return inputs;
         }
 
@@ -120,24 +143,25 @@ impl AudioNodeEngine for AudioBufferSourceNode {
 
         if self.playback_offset >= len || self.should_play_at(info.frame) == (false, true) {
             self.maybe_trigger_onended_callback();
-            inputs.blocks.push(Default::default());
+            inputs.blocks.push(Block::new(info.frames));
             return inputs;
         }
 
         let buffer = self.buffer.as_ref().unwrap();
 
+        let frames = Tick(info.frames as u64);
         let samples_to_copy = match self.stop_at {
             Some(stop_at) => {
                 let ticks_to_stop = stop_at - info.frame;
-                if ticks_to_stop > FRAMES_PER_BLOCK {
-                    FRAMES_PER_BLOCK.0 as usize
+                if ticks_to_stop > frames {
+                    frames.0 as usize
                 } else {
                     ticks_to_stop.0 as usize
                 }
             }
             None => {
-                if self.playback_offset + (FRAMES_PER_BLOCK.0 as usize) < len {
-                    FRAMES_PER_BLOCK.0 as usize
+                if self.playback_offset + (frames.0 as usize) < len {
+                    frames.0 as usize
                 } else {
                     len - self.playback_offset
                 }
@@ -146,16 +170,16 @@ impl AudioNodeEngine for AudioBufferSourceNode {
 
         let next_offset = self.playback_offset + samples_to_copy;
 
-        if samples_to_copy == FRAMES_PER_BLOCK.0 as usize {
+        if samples_to_copy == frames.0 as usize {
             // copy entire chan
-            let mut block = Block::empty();
+            let mut block = Block::empty(info.frames);
             for chan in 0..buffer.chans() {
                 block.push_chan(&buffer.buffers[chan as usize][self.playback_offset..next_offset]);
             }
             inputs.blocks.push(block)
         } else {
             // silent fill and copy
-            let mut block = Block::default();
+            let mut block = Block::new(info.frames);
             block.repeat(buffer.chans());
             block.explicit_repeat();
             for chan in 0..buffer.chans() {
@@ -172,11 +196,11 @@ impl AudioNodeEngine for AudioBufferSourceNode {
         inputs
     }
 
-    fn get_param(&mut self, id: ParamType) -> &mut Param {
+    fn get_param(&mut self, id: ParamType) -> AudioResult<&mut Param> {
         match id {
-            ParamType::PlaybackRate => &mut self.playback_rate,
-            ParamType::Detune => &mut self.detune,
-            _ => panic!("Unknown param {:?} for AudioBufferSourceNode", id),
+            ParamType::PlaybackRate => Ok(&mut self.playback_rate),
+            ParamType::Detune => Ok(&mut self.detune),
+            _ => Err(AudioError::InvalidAccess),
         }
     }
 
@@ -186,7 +210,7 @@ impl AudioNodeEngine for AudioBufferSourceNode {
     );
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioBuffer {
     /// Invariant: all buffers must be of the same length
     pub buffers: Vec<Vec<f32>>,
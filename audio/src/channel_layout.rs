@@ -0,0 +1,256 @@
+//! Channel layouts, and matrices for mixing between them.
+//!
+//! `ChannelInterpretation::Speakers` only knows the mixing rules of a few
+//! speaker layouts. A node can instead be given a `MixingMatrix`, which
+//! says how much of each channel of one layout goes into each channel of
+//! another. This allows e.g. decoding ambisonics to a speaker rig, or
+//! folding a surround layout the spec does not know about.
+
+use error::{AudioError, AudioResult};
+use node::MAX_CHANNEL_COUNT;
+
+/// The position of a channel within a layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelPosition {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    SideLeft,
+    SideRight,
+    BackLeft,
+    BackRight,
+    BackCenter,
+    FrontLeftOfCenter,
+    FrontRightOfCenter,
+    TopFrontLeft,
+    TopFrontRight,
+    TopBackLeft,
+    TopBackRight,
+    /// An ambisonic component, by its ACN index
+    Ambisonic(u8),
+    /// A channel with no particular position, by its index
+    Discrete(u8),
+}
+
+/// The positions of the channels of a block, in order
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelLayout {
+    positions: Vec<ChannelPosition>,
+}
+
+impl ChannelLayout {
+    /// A layout with the given positions. Fails with `NotSupported` if
+    /// there are no positions, more than `MAX_CHANNEL_COUNT`, or if a
+    /// position appears twice.
+    pub fn new(positions: Vec<ChannelPosition>) -> AudioResult<Self> {
+        let layout = ChannelLayout { positions };
+        layout.check()?;
+        Ok(layout)
+    }
+
+    pub fn mono() -> Self {
+        ChannelLayout {
+            positions: vec![ChannelPosition::FrontCenter],
+        }
+    }
+
+    pub fn stereo() -> Self {
+        use self::ChannelPosition::*;
+        ChannelLayout {
+            positions: vec![FrontLeft, FrontRight],
+        }
+    }
+
+    pub fn quad() -> Self {
+        use self::ChannelPosition::*;
+        ChannelLayout {
+            positions: vec![FrontLeft, FrontRight, SideLeft, SideRight],
+        }
+    }
+
+    pub fn surround_5_1() -> Self {
+        use self::ChannelPosition::*;
+        ChannelLayout {
+            positions: vec![
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                SideLeft,
+                SideRight,
+            ],
+        }
+    }
+
+    pub fn surround_7_1() -> Self {
+        use self::ChannelPosition::*;
+        ChannelLayout {
+            positions: vec![
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                SideLeft,
+                SideRight,
+                BackLeft,
+                BackRight,
+            ],
+        }
+    }
+
+    /// The layout `ChannelInterpretation::Speakers` assumes for
+    /// `channels` channels, or a discrete one if there is none
+    pub fn speakers(channels: u8) -> AudioResult<Self> {
+        match channels {
+            1 => Ok(Self::mono()),
+            2 => Ok(Self::stereo()),
+            4 => Ok(Self::quad()),
+            6 => Ok(Self::surround_5_1()),
+            8 => Ok(Self::surround_7_1()),
+            _ => Self::discrete(channels),
+        }
+    }
+
+    pub fn discrete(channels: u8) -> AudioResult<Self> {
+        Self::new((0..channels).map(ChannelPosition::Discrete).collect())
+    }
+
+    /// Ambisonics of the given order, with its (order + 1)² components
+    /// in ACN order
+    pub fn ambisonic(order: u8) -> AudioResult<Self> {
+        let channels = (order as u32 + 1) * (order as u32 + 1);
+        if channels > MAX_CHANNEL_COUNT as u32 {
+            return Err(AudioError::NotSupported);
+        }
+        Self::new((0..channels as u8).map(ChannelPosition::Ambisonic).collect())
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.positions.len() as u8
+    }
+
+    pub fn positions(&self) -> &[ChannelPosition] {
+        &self.positions
+    }
+
+    /// The index of the channel at the given position
+    pub fn index_of(&self, position: ChannelPosition) -> Option<u8> {
+        self.positions
+            .iter()
+            .position(|p| *p == position)
+            .map(|i| i as u8)
+    }
+
+    fn check(&self) -> AudioResult<()> {
+        let positions = &self.positions;
+        if positions.is_empty() || positions.len() > MAX_CHANNEL_COUNT as usize {
+            return Err(AudioError::NotSupported);
+        }
+        for (i, position) in positions.iter().enumerate() {
+            if positions[..i].contains(position) {
+                return Err(AudioError::NotSupported);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How much of each channel of a layout goes into each channel of another
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MixingMatrix {
+    from: ChannelLayout,
+    to: ChannelLayout,
+    /// One row for each output channel, with
+    /// the gain of each input channel
+    gains: Vec<f32>,
+}
+
+impl MixingMatrix {
+    /// A matrix passing through the positions found in both layouts,
+    /// and dropping the others
+    pub fn new(from: ChannelLayout, to: ChannelLayout) -> Self {
+        let mut gains = vec![0.; from.channels() as usize * to.channels() as usize];
+        for (output, position) in to.positions().iter().enumerate() {
+            if let Some(input) = from.index_of(*position) {
+                gains[output * from.channels() as usize + input as usize] = 1.;
+            }
+        }
+        MixingMatrix { from, to, gains }
+    }
+
+    /// A matrix with the given gains, one row of `from.channels()`
+    /// gains for each channel of `to`. Fails with `IndexSize` if there
+    /// are not as many gains.
+    pub fn with_gains(
+        from: ChannelLayout,
+        to: ChannelLayout,
+        gains: Vec<f32>,
+    ) -> AudioResult<Self> {
+        let matrix = MixingMatrix { from, to, gains };
+        matrix.check()?;
+        Ok(matrix)
+    }
+
+    pub fn from(&self) -> &ChannelLayout {
+        &self.from
+    }
+
+    pub fn to(&self) -> &ChannelLayout {
+        &self.to
+    }
+
+    pub fn inputs(&self) -> u8 {
+        self.from.channels()
+    }
+
+    pub fn outputs(&self) -> u8 {
+        self.to.channels()
+    }
+
+    /// The gain from a position of the input layout to a position of the
+    /// output layout. Fails with `IndexSize` if a layout does not have
+    /// the position.
+    pub fn gain(&self, to: ChannelPosition, from: ChannelPosition) -> AudioResult<f32> {
+        let idx = self.index(to, from)?;
+        Ok(self.gains[idx])
+    }
+
+    /// Set the gain from a position of the input layout to a position of
+    /// the output layout. Fails with `IndexSize` if a layout does not have
+    /// the position.
+    pub fn set_gain(
+        &mut self,
+        to: ChannelPosition,
+        from: ChannelPosition,
+        gain: f32,
+    ) -> AudioResult<()> {
+        let idx = self.index(to, from)?;
+        self.gains[idx] = gain;
+        Ok(())
+    }
+
+    /// The gains of the input channels for an output channel
+    pub fn row(&self, output: u8) -> &[f32] {
+        let inputs = self.inputs() as usize;
+        let start = output as usize * inputs;
+        &self.gains[start..start + inputs]
+    }
+
+    /// Make sure a matrix that did not go through the constructors,
+    /// e.g. a deserialized one, is consistent
+    pub(crate) fn check(&self) -> AudioResult<()> {
+        self.from.check()?;
+        self.to.check()?;
+        if self.gains.len() != self.inputs() as usize * self.outputs() as usize {
+            return Err(AudioError::IndexSize);
+        }
+        Ok(())
+    }
+
+    fn index(&self, to: ChannelPosition, from: ChannelPosition) -> AudioResult<usize> {
+        let output = self.to.index_of(to).ok_or(AudioError::IndexSize)?;
+        let input = self.from.index_of(from).ok_or(AudioError::IndexSize)?;
+        Ok(output as usize * self.inputs() as usize + input as usize)
+    }
+}
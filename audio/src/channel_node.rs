@@ -1,10 +1,10 @@
-use block::FRAMES_PER_BLOCK_USIZE;
 use block::{Block, Chunk};
-use node::AudioNodeType;
+use error::{AudioError, AudioResult};
+use node::{AudioNodeInit, AudioNodeType};
 use node::BlockInfo;
 use node::{AudioNodeEngine, ChannelCountMode, ChannelInfo, ChannelInterpretation};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct ChannelNodeOptions {
     pub channels: u8,
 }
@@ -33,16 +33,22 @@ impl AudioNodeEngine for ChannelMergerNode {
         AudioNodeType::ChannelMergerNode
     }
 
-    fn process(&mut self, mut inputs: Chunk, _: &BlockInfo) -> Chunk {
+    fn init_options(&self) -> AudioNodeInit {
+        AudioNodeInit::ChannelMergerNode(ChannelNodeOptions {
+            channels: self.channels,
+        })
+    }
+
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == self.channels as usize);
 
-        let mut block = Block::default();
+        let mut block = Block::new(info.frames);
         block.repeat(self.channels);
         block.explicit_repeat();
 
         for (i, channel) in block
             .data_mut()
-            .chunks_mut(FRAMES_PER_BLOCK_USIZE)
+            .chunks_mut(info.frames)
             .enumerate()
         {
             channel.copy_from_slice(inputs.blocks[i].data_mut())
@@ -57,12 +63,14 @@ impl AudioNodeEngine for ChannelMergerNode {
         self.channels as u32
     }
 
-    fn set_channel_count_mode(&mut self, _: ChannelCountMode) {
-        panic!("channel merger nodes cannot have their mode changed");
+    fn set_channel_count_mode(&mut self, _: ChannelCountMode) -> AudioResult<()> {
+        // channel merger nodes cannot have their mode changed
+        Err(AudioError::InvalidState)
     }
 
-    fn set_channel_count(&mut self, _: u8) {
-        panic!("channel merger nodes cannot have their channel count changed");
+    fn set_channel_count(&mut self, _: u8) -> AudioResult<()> {
+        // channel merger nodes cannot have their channel count changed
+        Err(AudioError::InvalidState)
     }
 }
 
@@ -88,13 +96,26 @@ impl AudioNodeEngine for ChannelSplitterNode {
         AudioNodeType::ChannelSplitterNode
     }
 
-    fn process(&mut self, mut inputs: Chunk, _: &BlockInfo) -> Chunk {
+    fn init_options(&self) -> AudioNodeInit {
+        AudioNodeInit::ChannelSplitterNode(ChannelNodeOptions {
+            channels: self.channel_count(),
+        })
+    }
+
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 1);
 
         let original = inputs.blocks.pop().unwrap();
 
+        if original.is_silence() {
+            for _ in 0..self.channel_count() {
+                inputs.blocks.push(Block::new(info.frames));
+            }
+            return inputs;
+        }
+
         for chan in 0..original.chan_count() {
-            let mut block = Block::empty();
+            let mut block = Block::empty(info.frames);
             block.push_chan(original.data_chan(chan));
             inputs.blocks.push(block);
         }
@@ -106,15 +127,18 @@ impl AudioNodeEngine for ChannelSplitterNode {
         self.channel_count() as u32
     }
 
-    fn set_channel_count_mode(&mut self, _: ChannelCountMode) {
-        panic!("channel splitter nodes cannot have their mode changed");
+    fn set_channel_count_mode(&mut self, _: ChannelCountMode) -> AudioResult<()> {
+        // channel splitter nodes cannot have their mode changed
+        Err(AudioError::InvalidState)
     }
 
-    fn set_channel_interpretation(&mut self, _: ChannelInterpretation) {
-        panic!("channel splitter nodes cannot have their channel interpretation changed");
+    fn set_channel_interpretation(&mut self, _: ChannelInterpretation) -> AudioResult<()> {
+        // channel splitter nodes cannot have their channel interpretation changed
+        Err(AudioError::InvalidState)
     }
 
-    fn set_channel_count(&mut self, _: u8) {
-        panic!("channel splitter nodes cannot have their channel count changed");
+    fn set_channel_count(&mut self, _: u8) -> AudioResult<()> {
+        // channel splitter nodes cannot have their channel count changed
+        Err(AudioError::InvalidState)
     }
 }
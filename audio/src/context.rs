@@ -1,13 +1,24 @@
 use AudioBackend;
+use block::{FRAMES_PER_BLOCK_USIZE, MAX_FRAMES_PER_BLOCK};
+use channel_layout::MixingMatrix;
+use control_queue::{self, ControlQueue, ControlSender};
 use decoder::{AudioDecoder, AudioDecoderCallbacks, AudioDecoderOptions};
-use graph::{AudioGraph, InputPort, NodeId, OutputPort, PortId};
-use node::{AudioNodeInit, AudioNodeMessage};
-use render_thread::AudioRenderThread;
+use error::{AudioError, AudioResult};
+use graph::{AudioGraph, Disconnection, InputPort, NodeId, NodeProfile, OutputPort, PortId};
+use node::{AudioNodeInit, AudioNodeMessage, MAX_CHANNEL_COUNT};
+use offline_sink::{OfflineOutput, OfflineRendering};
+use preset::GraphPreset;
+use render_capacity::RenderCapacityStats;
+use pull_renderer::PullRenderer;
+use render_thread::{AudioRenderThread, Sink};
 use render_thread::AudioRenderThreadMsg;
-use std::cell::Cell;
+use snapshot::GraphSnapshot;
+use std::collections::HashMap;
 use std::marker::PhantomData;
-use std::sync::mpsc::{self, Sender};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread::Builder;
+use std::time::Instant;
 
 /// Describes the state of the audio context on the control thread.
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -20,10 +31,54 @@ pub enum ProcessingState {
     /// The audio context has been released, and can no longer be used
     /// to process audio.
     Closed,
+    /// The sink failed, and audio is not processed until the
+    /// audio context is resumed.
+    Interrupted,
 }
 
 pub type StateChangeResult = Result<(), ()>;
 
+/// A time on the timeline of a context, with the time at which the audio
+/// of that time reached the output.
+///
+/// https://webaudio.github.io/web-audio-api/#dictdef-audiotimestamp
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioTimestamp {
+    /// Seconds on the timeline of the context
+    pub context_time: f64,
+    /// When the audio at `context_time` reached the output,
+    /// on the monotonic clock of the system
+    pub system_time: Instant,
+}
+
+/// What the render thread shares with the control thread,
+/// so that it can be read without a round-trip
+#[derive(Clone, Copy)]
+pub(crate) struct SharedState {
+    pub state: ProcessingState,
+    /// The context time up to which audio was handed to the sink
+    pub rendered_time: f64,
+    /// When that happened
+    pub rendered_at: Instant,
+    /// Seconds between handing audio to the sink and it being heard
+    pub output_latency: f64,
+    /// The context time of the audio being heard, as reported by the
+    /// sink along with the handing of audio, and when that was
+    pub output_position: Option<(f64, Instant)>,
+}
+
+impl SharedState {
+    fn new() -> Self {
+        SharedState {
+            state: ProcessingState::Suspended,
+            rendered_time: 0.,
+            rendered_at: Instant::now(),
+            output_latency: 0.,
+            output_position: None,
+        }
+    }
+}
+
 /// Identify the type of playback, which affects tradeoffs between audio output
 /// and power consumption.
 pub enum LatencyCategory {
@@ -42,6 +97,15 @@ pub struct RealTimeAudioContextOptions {
     pub sample_rate: f32,
     /// Type of playback.
     pub latency_hint: LatencyCategory,
+    /// Number of threads used for processing the audio graph.
+    /// With more than one, independent parts of the graph are
+    /// processed in parallel.
+    pub render_threads: usize,
+    /// How messages are sent to the render thread.
+    pub control_queue: ControlQueue,
+    /// Number of frames in a render quantum. Larger quanta are processed
+    /// more efficiently, at the cost of latency.
+    pub render_size: usize,
 }
 
 impl Default for RealTimeAudioContextOptions {
@@ -49,6 +113,9 @@ impl Default for RealTimeAudioContextOptions {
         Self {
             sample_rate: 44100.,
             latency_hint: LatencyCategory::Interactive,
+            render_threads: 1,
+            control_queue: Default::default(),
+            render_size: FRAMES_PER_BLOCK_USIZE,
         }
     }
 }
@@ -58,9 +125,20 @@ pub struct OfflineAudioContextOptions {
     /// The number of channels for this offline audio context.
     pub channels: u8,
     /// The length of the rendered audio buffer in sample-frames.
+    /// Contexts cannot be made with the default of 0.
     pub length: usize,
     /// Number of samples that will be rendered in one second, measured in Hz.
     pub sample_rate: f32,
+    /// Number of threads used for processing the audio graph.
+    /// With more than one, independent parts of the graph are
+    /// processed in parallel.
+    pub render_threads: usize,
+    /// How messages are sent to the render thread.
+    pub control_queue: ControlQueue,
+    /// Number of frames in a render quantum.
+    pub render_size: usize,
+    /// Where the rendered audio goes.
+    pub output: OfflineOutput,
 }
 
 impl Default for OfflineAudioContextOptions {
@@ -69,6 +147,10 @@ impl Default for OfflineAudioContextOptions {
             channels: 1,
             length: 0,
             sample_rate: 44100.,
+            render_threads: 1,
+            control_queue: Default::default(),
+            render_size: FRAMES_PER_BLOCK_USIZE,
+            output: Default::default(),
         }
     }
 }
@@ -100,47 +182,166 @@ impl Default for AudioContextOptions {
 /// Representation of an audio context on the control thread.
 pub struct AudioContext<B> {
     /// Rendering thread communication channel.
-    sender: Sender<AudioRenderThreadMsg>,
-    /// State of the audio context on the control thread.
-    state: Cell<ProcessingState>,
+    sender: ControlSender,
+    /// State of the audio context and of its output,
+    /// as set by the render thread.
+    shared: Arc<Mutex<SharedState>>,
     /// Number of samples that will be played in one second.
     sample_rate: f32,
+    /// Number of frames in a render quantum.
+    render_size: usize,
     /// The identifier of an AudioDestinationNode with a single input
     /// representing the final destination for all audio.
     dest_node: NodeId,
+    /// Where the render thread reports node messages that failed
+    message_errors: Sender<AudioResult<()>>,
+    failed_messages: Receiver<AudioResult<()>>,
     backend: PhantomData<B>,
 }
 
+/// Fail with `NotSupported` for a render quantum of no
+/// frames or of more than `MAX_FRAMES_PER_BLOCK`
+pub(crate) fn check_render_size(render_size: usize) -> AudioResult<()> {
+    if render_size == 0 || render_size > MAX_FRAMES_PER_BLOCK {
+        return Err(AudioError::NotSupported);
+    }
+    Ok(())
+}
+
+/// Fail with `NotSupported` for rendering no channels
+/// or more than `MAX_CHANNEL_COUNT`
+pub(crate) fn check_channels(channels: u8) -> AudioResult<()> {
+    if channels == 0 || channels > MAX_CHANNEL_COUNT {
+        return Err(AudioError::NotSupported);
+    }
+    Ok(())
+}
+
 impl<B: AudioBackend + 'static> AudioContext<B> {
-    /// Constructs a new audio context.
-    pub fn new(options: AudioContextOptions) -> Self {
-        let sample_rate = match options {
-            AudioContextOptions::RealTimeAudioContext(ref options) => options.sample_rate,
-            AudioContextOptions::OfflineAudioContext(ref options) => options.sample_rate
+    /// Constructs a new audio context, failing with `NotSupported` for
+    /// a render size or a number of offline channels out of range, and
+    /// for an offline context with nothing to render.
+    pub fn new(options: AudioContextOptions) -> AudioResult<Self> {
+        let (sample_rate, control_queue, render_size) = match options {
+            AudioContextOptions::RealTimeAudioContext(ref options) => {
+                (options.sample_rate, options.control_queue, options.render_size)
+            }
+            AudioContextOptions::OfflineAudioContext(ref options) => {
+                (options.sample_rate, options.control_queue, options.render_size)
+            }
         };
+        check_render_size(render_size)?;
+        if let AudioContextOptions::OfflineAudioContext(ref options) = options {
+            check_channels(options.channels)?;
+            if options.length == 0 {
+                return Err(AudioError::NotSupported);
+            }
+        }
 
-        let (sender, receiver) = mpsc::channel();
-        let sender_ = sender.clone();
+        let (sender, receiver) = control_queue::channel(control_queue);
+        let sender_ = sender.channel();
         let graph = AudioGraph::new();
         let dest_node = graph.dest_id();
+        let shared = Arc::new(Mutex::new(SharedState::new()));
+        let shared_ = shared.clone();
+        let (message_errors, failed_messages) = mpsc::channel();
         Builder::new()
             .name("AudioRenderThread".to_owned())
             .spawn(move || {
-                AudioRenderThread::<B>::start(receiver, sender_, sample_rate, graph, options)
-                    .expect("Could not start AudioRenderThread");
+                AudioRenderThread::<B>::start(
+                    receiver,
+                    sender_,
+                    sample_rate,
+                    graph,
+                    options,
+                    shared_,
+                ).expect("Could not start AudioRenderThread");
             })
             .unwrap();
-        Self {
+        Ok(Self {
             sender,
-            state: Cell::new(ProcessingState::Suspended),
+            shared,
             sample_rate,
+            render_size,
             dest_node,
+            message_errors,
+            failed_messages,
             backend: PhantomData,
-        }
+        })
     }
 
+    /// Constructs an audio context whose audio is not played by a sink of
+    /// the backend, but rendered on demand by the returned `PullRenderer`,
+    /// typically from the audio callback of a host.
+    ///
+    /// Until the renderer starts, the context handles its calls on the
+    /// calling thread, so the graph can be built before the host stream is
+    /// started. Callbacks like those of `on_state_change` then run on the
+    /// calling thread too. Once the renderer has started, calls waiting on
+    /// an answer must not be made from the audio callback of the host, since
+    /// they are only answered by `PullRenderer::render_into`.
+    ///
+    /// Fails with `NotSupported` for a render size out of range.
+    pub fn new_pull(options: RealTimeAudioContextOptions) -> AudioResult<(Self, PullRenderer<B>)>
+    where
+        B::Sink: Send,
+    {
+        check_render_size(options.render_size)?;
+        let (mut sender, receiver) = control_queue::channel(options.control_queue);
+        let mut graph = AudioGraph::new();
+        let dest_node = graph.dest_id();
+        // Spawning threads has no place in the audio callback of the host,
+        // the renderer only joins the workers from there
+        graph.spawn_workers(options.render_threads);
+        let shared = Arc::new(Mutex::new(SharedState::new()));
+        let thread = AudioRenderThread::new(
+            graph,
+            Sink::Pull,
+            options.sample_rate,
+            options.render_size,
+            shared.clone(),
+        );
+        let renderer = PullRenderer::new(thread, receiver);
+        sender.set_handler(renderer.handler());
+        let (message_errors, failed_messages) = mpsc::channel();
+        let context = Self {
+            sender,
+            shared,
+            sample_rate: options.sample_rate,
+            render_size: options.render_size,
+            dest_node,
+            message_errors,
+            failed_messages,
+            backend: PhantomData,
+        };
+        Ok((context, renderer))
+    }
+
+    /// The state of the context, which the render thread sets
+    /// before answering a state change
     pub fn state(&self) -> ProcessingState {
-        self.state.get()
+        self.shared.lock().unwrap().state
+    }
+
+    /// Call `callback` on the render thread whenever the state of the
+    /// context changes, e.g. to fire `onstatechange`. This includes the
+    /// changes made by the render thread itself: offline contexts are
+    /// suspended by `suspend_at` and closed once rendered, and contexts
+    /// are interrupted when the sink fails.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-baseaudiocontext-onstatechange
+    pub fn on_state_change<F>(&self, callback: F)
+    where
+        F: Fn(ProcessingState) + Send + 'static,
+    {
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::OnStateChange(Box::new(callback)));
+    }
+
+    /// Number of frames in a render quantum of this context
+    pub fn render_size(&self) -> usize {
+        self.render_size
     }
 
     pub fn dest_node(&self) -> NodeId {
@@ -153,31 +354,240 @@ impl<B: AudioBackend + 'static> AudioContext<B> {
         rx.recv().unwrap()
     }
 
-    pub fn create_node(&self, node_type: AudioNodeInit) -> NodeId {
+    /// Seconds between the destination getting audio and the sink getting
+    /// it, as the render thread renders a quantum ahead of the sink
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-baselatency
+    pub fn base_latency(&self) -> f64 {
+        self.render_size as f64 / self.sample_rate as f64
+    }
+
+    /// Seconds between the sink getting audio and it being heard, as last
+    /// reported by the sink. Offline sinks have none.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-outputlatency
+    pub fn output_latency(&self) -> f64 {
+        self.shared.lock().unwrap().output_latency
+    }
+
+    /// The time of the context whose audio reached the output at some
+    /// recent instant, to map between the clock of the context and the
+    /// clock of the system. This uses the playback position of the sink
+    /// when it has one, and does not wait on the render thread.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-getoutputtimestamp
+    pub fn output_timestamp(&self) -> AudioTimestamp {
+        let shared = *self.shared.lock().unwrap();
+        if let Some((context_time, system_time)) = shared.output_position {
+            return AudioTimestamp {
+                context_time,
+                system_time,
+            };
+        }
+        // Otherwise, the audio handed to the sink last is heard after both
+        // latencies at best, so the audio heard at that moment is at least
+        // that much older. This misses whatever the sink has queued.
+        let latency = self.base_latency() + shared.output_latency;
+        AudioTimestamp {
+            context_time: (shared.rendered_time - latency).max(0.),
+            system_time: shared.rendered_at,
+        }
+    }
+
+    /// How loaded the render thread has been since the previous call,
+    /// see `RenderCapacityStats`. Calling this at regular intervals gives
+    /// the periodic updates of WebAudio's `renderCapacity`.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#AudioRenderCapacity
+    pub fn render_capacity(&self) -> RenderCapacityStats {
         let (tx, rx) = mpsc::channel();
         let _ = self
             .sender
-            .send(AudioRenderThreadMsg::CreateNode(node_type, tx));
+            .send(AudioRenderThreadMsg::GetRenderCapacity(tx));
+        rx.recv().unwrap()
+    }
+
+    /// Start or stop timing how long each node takes to process,
+    /// resetting the timings gathered so far.
+    pub fn set_profiling(&self, profiling: bool) {
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::SetProfiling(profiling));
+    }
+
+    /// The time a node has spent processing since profiling started,
+    /// failing with `InvalidAccess` for unknown nodes.
+    pub fn node_profile(&self, node: NodeId) -> AudioResult<NodeProfile> {
+        let (tx, rx) = mpsc::channel();
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::GetNodeProfile(node, tx));
+        rx.recv().unwrap_or(Err(AudioError::InvalidState))
+    }
+
+    /// Take a snapshot of the audio graph as it is on the render thread,
+    /// for inspecting the nodes of this context and how they are connected.
+    pub fn graph_snapshot(&self) -> GraphSnapshot {
+        let (tx, rx) = mpsc::channel();
+        let _ = self.sender.send(AudioRenderThreadMsg::GetGraphSnapshot(tx));
+        rx.recv().unwrap()
+    }
+
+    /// Save the nodes of this context and how they are connected,
+    /// for restoring them later with `load_preset`.
+    pub fn save_preset(&self) -> GraphPreset {
+        let (tx, rx) = mpsc::channel();
+        let _ = self.sender.send(AudioRenderThreadMsg::SavePreset(tx));
         rx.recv().unwrap()
     }
 
+    /// Add the nodes and connections of a preset to this context.
+    ///
+    /// Returns the id of each node of the preset in this context, keyed
+    /// by its id in the preset. The destination of the preset becomes the
+    /// destination of this context. Fails with `NotSupported` for node
+    /// types that cannot be created, and otherwise like `connect_ports`
+    /// and the channel setters would, in which case nothing of the preset
+    /// is added.
+    pub fn load_preset(&self, preset: GraphPreset) -> AudioResult<HashMap<NodeId, NodeId>> {
+        let (tx, rx) = mpsc::channel();
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::LoadPreset(preset, tx));
+        rx.recv().unwrap_or(Err(AudioError::InvalidState))
+    }
+
+    /// Start a batch of changes to the audio graph, which are applied
+    /// together between two render quanta once committed.
+    pub fn batch(&self) -> Batch<B> {
+        let (results, errors) = mpsc::channel();
+        Batch {
+            context: self,
+            msgs: Vec::new(),
+            results,
+            errors,
+        }
+    }
+
+    /// Create a node, failing with `NotSupported` for node types that
+    /// cannot be created yet and `IndexSize` for channel mergers and
+    /// splitters with no channels or more than `MAX_CHANNEL_COUNT`.
+    pub fn create_node(&self, node_type: AudioNodeInit) -> AudioResult<NodeId> {
+        let (tx, rx) = mpsc::channel();
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::CreateNode(node_type, tx));
+        rx.recv().unwrap_or(Err(AudioError::InvalidState))
+    }
+
     /// Resume audio processing.
-    make_state_change!(resume, Running, Resume);
+    make_state_change!(resume, Resume);
 
     /// Suspend audio processing.
-    make_state_change!(suspend, Suspended, Suspend);
+    make_state_change!(suspend, Suspend);
 
     /// Stop audio processing and close render thread.
-    make_state_change!(close, Closed, Close);
+    make_state_change!(close, Close);
+
+    /// Start rendering an offline context, returning a handle to the
+    /// rendered audio. Fails with `InvalidState` for real time contexts,
+    /// and if rendering was already started.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-offlineaudiocontext-startrendering
+    pub fn start_rendering(&self) -> AudioResult<OfflineRendering> {
+        let (sender, receiver) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::StartRendering(sender, tx));
+        rx.recv().unwrap_or(Err(AudioError::InvalidState))?;
+        Ok(OfflineRendering::new(receiver, self.sample_rate))
+    }
 
+    /// Suspend rendering of an offline context once it reaches `time`,
+    /// rounded up to a render quantum, so that the graph can be changed
+    /// at that point. `callback` is called when this happens, and
+    /// rendering goes on after a `resume`.
+    ///
+    /// **The callback runs on the render thread**, which waits for it to
+    /// return. Calls of the context that wait for an answer, which is most
+    /// of them including `resume`, deadlock if made from the callback. Have
+    /// it notify another thread, and make the changes from there.
+    ///
+    /// Fails with `InvalidState` for real time contexts, for a time that is
+    /// not finite, was already rendered or is past the end of the rendering,
+    /// and if there already is a suspend at that time.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-offlineaudiocontext-suspend
+    pub fn suspend_at<F>(&self, time: f64, callback: F) -> AudioResult<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let _ = self.sender.send(AudioRenderThreadMsg::SuspendAt(
+            time,
+            Box::new(callback),
+            tx,
+        ));
+        rx.recv().unwrap_or(Err(AudioError::InvalidState))
+    }
+
+    /// Send a message to a node, without waiting for it to be handled.
+    /// Failures are reported by `check_messages`.
     pub fn message_node(&self, id: NodeId, msg: AudioNodeMessage) {
-        let _ = self.sender.send(AudioRenderThreadMsg::MessageNode(id, msg));
+        let _ = self.sender.send(AudioRenderThreadMsg::MessageNode(
+            id,
+            msg,
+            self.message_errors.clone(),
+        ));
+    }
+
+    /// The error of the first node message that failed since the last call.
+    ///
+    /// Messages fail with `InvalidAccess` for unknown nodes or params the
+    /// node does not have, and like the node's setters would for invalid
+    /// channel settings. They are handled between render quanta, so the
+    /// latest ones may only be reported by a later call.
+    pub fn check_messages(&self) -> AudioResult<()> {
+        // go over all of them, so that the next call starts afresh
+        self.failed_messages
+            .try_iter()
+            .fold(Ok(()), |first, failure| first.and(failure))
     }
 
-    pub fn connect_ports(&self, from: PortId<OutputPort>, to: PortId<InputPort>) {
+    /// Mix the inputs of a node that have as many channels as the matrix
+    /// has inputs through the matrix, before the usual mixing to the
+    /// channel count of the node. Inputs with another channel count skip
+    /// the matrix. `None` goes back to the usual mixing.
+    ///
+    /// Fails like `AudioGraph::set_mixing_matrix`.
+    pub fn set_mixing_matrix(
+        &self,
+        id: NodeId,
+        matrix: Option<MixingMatrix>,
+    ) -> AudioResult<()> {
+        let (tx, rx) = mpsc::channel();
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::SetMixingMatrix(id, matrix, tx));
+        rx.recv().unwrap_or(Err(AudioError::InvalidState))
+    }
+
+    /// Connect an output port to an input port or param.
+    ///
+    /// The connection is validated on the render thread, failing with
+    /// `IndexSize` for ports the nodes do not have and `InvalidAccess`
+    /// for unknown nodes or params.
+    pub fn connect_ports(
+        &self,
+        from: PortId<OutputPort>,
+        to: PortId<InputPort>,
+    ) -> AudioResult<()> {
+        let (tx, rx) = mpsc::channel();
         let _ = self
             .sender
-            .send(AudioRenderThreadMsg::ConnectPorts(from, to));
+            .send(AudioRenderThreadMsg::ConnectPorts(from, to, tx));
+        rx.recv().unwrap_or(Err(AudioError::InvalidState))
     }
 
     pub fn disconnect_all_from(&self, node: NodeId) {
@@ -231,6 +641,70 @@ impl<B: AudioBackend + 'static> AudioContext<B> {
             .send(AudioRenderThreadMsg::DisconnectOutputBetweenTo(out, inp));
     }
 
+    /// Connect an output port to an input port or param at the given
+    /// context time, in seconds.
+    ///
+    /// The connection is made at the start of the render quantum containing
+    /// that time, fading in over the quantum. The ports are checked right
+    /// away, failing like `connect_ports` would.
+    pub fn connect_ports_at(
+        &self,
+        time: f64,
+        from: PortId<OutputPort>,
+        to: PortId<InputPort>,
+    ) -> AudioResult<()> {
+        let (tx, rx) = mpsc::channel();
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::ConnectPortsAt(time, from, to, tx));
+        rx.recv().unwrap_or(Err(AudioError::InvalidState))
+    }
+
+    /// Disconnect connections at the given context time, in seconds.
+    ///
+    /// The connections are faded out over the render quantum containing
+    /// that time, and removed after it.
+    pub fn disconnect_at(&self, time: f64, disconnection: Disconnection) {
+        let _ = self
+            .sender
+            .send(AudioRenderThreadMsg::DisconnectAt(time, disconnection));
+    }
+
+    /// `disconnect_all_from` at the given time, see `disconnect_at`
+    pub fn disconnect_all_from_at(&self, time: f64, node: NodeId) {
+        self.disconnect_at(time, Disconnection::AllFrom(node))
+    }
+
+    /// `disconnect_output` at the given time, see `disconnect_at`
+    pub fn disconnect_output_at(&self, time: f64, out: PortId<OutputPort>) {
+        self.disconnect_at(time, Disconnection::Output(out))
+    }
+
+    /// `disconnect_between` at the given time, see `disconnect_at`
+    pub fn disconnect_between_at(&self, time: f64, from: NodeId, to: NodeId) {
+        self.disconnect_at(time, Disconnection::Between(from, to))
+    }
+
+    /// `disconnect_to` at the given time, see `disconnect_at`
+    pub fn disconnect_to_at(&self, time: f64, from: NodeId, to: PortId<InputPort>) {
+        self.disconnect_at(time, Disconnection::To(from, to))
+    }
+
+    /// `disconnect_output_between` at the given time, see `disconnect_at`
+    pub fn disconnect_output_between_at(&self, time: f64, out: PortId<OutputPort>, to: NodeId) {
+        self.disconnect_at(time, Disconnection::OutputBetween(out, to))
+    }
+
+    /// `disconnect_output_between_to` at the given time, see `disconnect_at`
+    pub fn disconnect_output_between_to_at(
+        &self,
+        time: f64,
+        out: PortId<OutputPort>,
+        inp: PortId<InputPort>,
+    ) {
+        self.disconnect_at(time, Disconnection::OutputBetweenTo(out, inp))
+    }
+
     /// Asynchronously decodes the audio file data contained in the given
     /// buffer.
     pub fn decode_audio_data(&self, data: Vec<u8>, callbacks: AudioDecoderCallbacks) {
@@ -253,6 +727,94 @@ impl<B: AudioBackend + 'static> AudioContext<B> {
     }
 }
 
+/// Changes to the audio graph that are applied all at once, so that
+/// no render quantum is processed with only some of them done.
+///
+/// Nothing happens until the batch is committed, and dropping the batch
+/// discards the changes. Nodes are the exception, as they are created
+/// right away for their ids to be used in the batch. They are not heard
+/// until connected, so this makes no difference to the output.
+pub struct Batch<'a, B: 'a> {
+    context: &'a AudioContext<B>,
+    msgs: Vec<AudioRenderThreadMsg>,
+    /// Where the connections of the batch report back to
+    results: Sender<AudioResult<()>>,
+    errors: Receiver<AudioResult<()>>,
+}
+
+impl<'a, B: AudioBackend + 'static> Batch<'a, B> {
+    pub fn create_node(&mut self, node_type: AudioNodeInit) -> AudioResult<NodeId> {
+        self.context.create_node(node_type)
+    }
+
+    /// Send a message to a node. Failures are reported when committing.
+    pub fn message_node(&mut self, id: NodeId, msg: AudioNodeMessage) {
+        self.msgs.push(AudioRenderThreadMsg::MessageNode(
+            id,
+            msg,
+            self.results.clone(),
+        ));
+    }
+
+    /// Connect an output port to an input port or param.
+    /// Failures are reported when committing.
+    pub fn connect_ports(&mut self, from: PortId<OutputPort>, to: PortId<InputPort>) {
+        self.msgs.push(AudioRenderThreadMsg::ConnectPorts(
+            from,
+            to,
+            self.results.clone(),
+        ));
+    }
+
+    pub fn disconnect_all_from(&mut self, node: NodeId) {
+        self.msgs.push(AudioRenderThreadMsg::DisconnectAllFrom(node));
+    }
+
+    pub fn disconnect_output(&mut self, out: PortId<OutputPort>) {
+        self.msgs.push(AudioRenderThreadMsg::DisconnectOutput(out));
+    }
+
+    pub fn disconnect_between(&mut self, from: NodeId, to: NodeId) {
+        self.msgs
+            .push(AudioRenderThreadMsg::DisconnectBetween(from, to));
+    }
+
+    pub fn disconnect_to(&mut self, from: NodeId, to: PortId<InputPort>) {
+        self.msgs.push(AudioRenderThreadMsg::DisconnectTo(from, to));
+    }
+
+    pub fn disconnect_output_between(&mut self, out: PortId<OutputPort>, to: NodeId) {
+        self.msgs
+            .push(AudioRenderThreadMsg::DisconnectOutputBetween(out, to));
+    }
+
+    pub fn disconnect_output_between_to(&mut self, out: PortId<OutputPort>, inp: PortId<InputPort>) {
+        self.msgs
+            .push(AudioRenderThreadMsg::DisconnectOutputBetweenTo(out, inp));
+    }
+
+    /// Apply the changes of this batch, in the order they were made,
+    /// and wait for them to be done.
+    ///
+    /// A change that fails does not stop the others from being applied.
+    /// The error of the first one to fail is returned.
+    pub fn commit(self) -> AudioResult<()> {
+        let Batch {
+            context,
+            msgs,
+            results,
+            errors,
+        } = self;
+        drop(results);
+        let (tx, rx) = mpsc::channel();
+        let _ = context
+            .sender
+            .send(AudioRenderThreadMsg::Batch(msgs, tx));
+        rx.recv().map_err(|_| AudioError::InvalidState)?;
+        errors.try_iter().collect()
+    }
+}
+
 impl<T> Drop for AudioContext<T> {
     fn drop(&mut self) {
         let (tx, _) = mpsc::channel();
@@ -0,0 +1,259 @@
+//! The queue carrying messages from the control thread to the render thread.
+//!
+//! By default this is an `mpsc` channel. Optionally, the messages of the
+//! audio context can go through a bounded lock-free ring buffer instead, so
+//! that the render thread never waits on a lock to pick them up. The channel
+//! is still used by the sink, and for waking the render thread up when it is
+//! waiting for messages.
+
+use render_thread::AudioRenderThreadMsg;
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SendError, Sender};
+use std::sync::Arc;
+use std::thread;
+
+/// How messages are sent from the control thread to the render thread
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlQueue {
+    /// An `mpsc` channel
+    Channel,
+    /// A lock-free queue holding up to `capacity` messages. Sending
+    /// waits for the render thread to make room once it is full.
+    LockFree { capacity: usize },
+}
+
+impl Default for ControlQueue {
+    fn default() -> Self {
+        ControlQueue::Channel
+    }
+}
+
+/// Create a queue of the given kind
+pub fn channel(kind: ControlQueue) -> (ControlSender, ControlReceiver) {
+    let (tx, rx) = mpsc::channel();
+    let (producer, consumer) = match kind {
+        ControlQueue::Channel => (None, None),
+        ControlQueue::LockFree { capacity } => {
+            let (producer, consumer) = ring(capacity);
+            (Some(producer), Some(consumer))
+        }
+    };
+    (
+        ControlSender {
+            tx,
+            producer,
+            handler: None,
+        },
+        ControlReceiver { rx, consumer },
+    )
+}
+
+/// The control thread end of the queue
+pub struct ControlSender {
+    tx: Sender<AudioRenderThreadMsg>,
+    producer: Option<Producer<AudioRenderThreadMsg>>,
+    /// Called after sending a message, see `set_handler`
+    handler: Option<Box<Fn() + Send>>,
+}
+
+impl ControlSender {
+    pub fn send(&self, msg: AudioRenderThreadMsg) -> Result<(), SendError<AudioRenderThreadMsg>> {
+        let producer = match self.producer {
+            Some(ref producer) => producer,
+            None => {
+                self.tx.send(msg)?;
+                self.handle();
+                return Ok(());
+            }
+        };
+        let mut msg = msg;
+        loop {
+            match producer.push(msg) {
+                Ok(()) => break,
+                Err(rejected) => {
+                    if producer.ring.closed.load(Ordering::Acquire) {
+                        return Err(SendError(rejected));
+                    }
+                    msg = rejected;
+                    // the render thread may be waiting, unaware of the backlog
+                    self.wake(producer);
+                    self.handle();
+                    thread::yield_now();
+                }
+            }
+        }
+        self.wake(producer);
+        self.handle();
+        Ok(())
+    }
+
+    /// Have `handler` called on the sending thread after each message is
+    /// queued, so that the messages can be handled there while there is no
+    /// render thread to pick them up
+    pub fn set_handler(&mut self, handler: Box<Fn() + Send>) {
+        self.handler = Some(handler);
+    }
+
+    fn handle(&self) {
+        if let Some(ref handler) = self.handler {
+            handler();
+        }
+    }
+
+    /// A channel to the render thread, for the sink to notify it
+    pub fn channel(&self) -> Sender<AudioRenderThreadMsg> {
+        self.tx.clone()
+    }
+
+    fn wake(&self, producer: &Producer<AudioRenderThreadMsg>) {
+        // pairs with the fence in `ControlReceiver::recv`, so that either
+        // the render thread sees the message, or we see it waiting
+        atomic::fence(Ordering::SeqCst);
+        if producer.ring.parked.swap(false, Ordering::SeqCst) {
+            let _ = self.tx.send(AudioRenderThreadMsg::Wake);
+        }
+    }
+}
+
+/// The render thread end of the queue
+pub struct ControlReceiver {
+    rx: Receiver<AudioRenderThreadMsg>,
+    consumer: Option<Consumer<AudioRenderThreadMsg>>,
+}
+
+impl ControlReceiver {
+    /// Take the next message, if any, without waiting
+    pub fn try_recv(&self) -> Option<AudioRenderThreadMsg> {
+        if let Some(ref consumer) = self.consumer {
+            if let Some(msg) = consumer.pop() {
+                return Some(msg);
+            }
+        }
+        self.rx.try_recv().ok()
+    }
+
+    /// Wait for the next message. Returns `None` once
+    /// there are no senders left.
+    pub fn recv(&self) -> Option<AudioRenderThreadMsg> {
+        let consumer = match self.consumer {
+            Some(ref consumer) => consumer,
+            None => return self.rx.recv().ok(),
+        };
+        consumer.ring.parked.store(true, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        if let Some(msg) = consumer.pop() {
+            consumer.ring.parked.store(false, Ordering::SeqCst);
+            return Some(msg);
+        }
+        let msg = self.rx.recv().ok();
+        consumer.ring.parked.store(false, Ordering::SeqCst);
+        msg
+    }
+}
+
+/// A bounded single-producer single-consumer queue
+struct Ring<T> {
+    slots: Box<[UnsafeCell<Option<T>>]>,
+    /// Number of items popped so far, only written by the consumer
+    head: AtomicUsize,
+    /// Number of items pushed so far, only written by the producer
+    tail: AtomicUsize,
+    /// Whether the consumer is waiting on the channel for a wake up
+    parked: AtomicBool,
+    /// Whether the producer is in the middle of a push
+    pushing: AtomicBool,
+    /// Whether the consumer is gone
+    closed: AtomicBool,
+}
+
+// Slots are only accessed by one side at a time, as told by head and tail
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "The control queue needs room for at least one message");
+    let ring = Arc::new(Ring {
+        slots: (0..capacity).map(|_| UnsafeCell::new(None)).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        parked: AtomicBool::new(false),
+        pushing: AtomicBool::new(false),
+        closed: AtomicBool::new(false),
+    });
+    (
+        Producer {
+            ring: ring.clone(),
+            not_sync: PhantomData,
+        },
+        Consumer {
+            ring,
+            not_sync: PhantomData,
+        },
+    )
+}
+
+// Both ends can be sent to another thread, but not shared, which
+// is what ensures there is a single producer and a single consumer
+struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    not_sync: PhantomData<Cell<()>>,
+}
+
+struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+    not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T> Producer<T> {
+    /// Push an item, giving it back if the queue is full or closed
+    fn push(&self, item: T) -> Result<(), T> {
+        let ring = &*self.ring;
+        // pairs with `Consumer::drop`: either we see the queue closed and
+        // keep the item, or the consumer waits for the push to complete
+        // and drops the item along with the rest of the queue
+        ring.pushing.store(true, Ordering::SeqCst);
+        if ring.closed.load(Ordering::SeqCst) {
+            ring.pushing.store(false, Ordering::SeqCst);
+            return Err(item);
+        }
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == ring.slots.len() {
+            ring.pushing.store(false, Ordering::SeqCst);
+            return Err(item);
+        }
+        unsafe {
+            *ring.slots[tail % ring.slots.len()].get() = Some(item);
+        }
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        ring.pushing.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl<T> Consumer<T> {
+    fn pop(&self) -> Option<T> {
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let item = unsafe { (*ring.slots[head % ring.slots.len()].get()).take() };
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        item
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::SeqCst);
+        while self.ring.pushing.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        // Drop whatever is left now rather than when the producer goes
+        // away, so that callers waiting on a response see it disconnect
+        while self.pop().is_some() {}
+    }
+}
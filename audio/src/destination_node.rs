@@ -1,5 +1,6 @@
 use block::Chunk;
-use node::{AudioNodeEngine, BlockInfo};
+use error::{AudioError, AudioResult};
+use node::{AudioNodeEngine, AudioNodeInit, BlockInfo};
 use node::{AudioNodeType, ChannelCountMode, ChannelInfo};
 
 #[derive(AudioNodeCommon)]
@@ -25,6 +26,10 @@ impl AudioNodeEngine for DestinationNode {
         AudioNodeType::DestinationNode
     }
 
+    fn init_options(&self) -> AudioNodeInit {
+        AudioNodeInit::DestinationNode
+    }
+
     fn process(&mut self, inputs: Chunk, _: &BlockInfo) -> Chunk {
         self.chunk = Some(inputs);
         Chunk::default()
@@ -34,11 +39,17 @@ impl AudioNodeEngine for DestinationNode {
         self.chunk.take()
     }
 
+    fn is_active(&self, _: &BlockInfo) -> bool {
+        // there is always data to hand over to the sink, even if it is silence
+        true
+    }
+
     fn output_count(&self) -> u32 {
         0
     }
 
-    fn set_channel_count_mode(&mut self, _: ChannelCountMode) {
-        panic!("destination nodes cannot have their mode changed");
+    fn set_channel_count_mode(&mut self, _: ChannelCountMode) -> AudioResult<()> {
+        // destination nodes cannot have their mode changed
+        Err(AudioError::InvalidState)
     }
 }
@@ -0,0 +1,45 @@
+use std::error::Error;
+use std::fmt;
+
+/// Errors that can be raised by operations on the audio graph.
+///
+/// These mirror the DOM exceptions WebAudio throws, so that the embedder
+/// can map them back to script without losing information.
+///
+/// https://heycam.github.io/webidl/#idl-DOMException-error-names
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioError {
+    /// An index or size is negative or greater than the allowed amount,
+    /// e.g. a port index that the node does not have.
+    IndexSize,
+    /// The object is in an invalid state, e.g. trying to change the
+    /// channel count of a node that has it fixed.
+    InvalidState,
+    /// The operation is not supported, e.g. a channel count of zero.
+    NotSupported,
+    /// The object does not support the operation or argument, e.g.
+    /// addressing a param the node does not have.
+    InvalidAccess,
+}
+
+pub type AudioResult<T> = Result<T, AudioError>;
+
+impl AudioError {
+    /// The name of the DOM exception this error corresponds to.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AudioError::IndexSize => "IndexSizeError",
+            AudioError::InvalidState => "InvalidStateError",
+            AudioError::NotSupported => "NotSupportedError",
+            AudioError::InvalidAccess => "InvalidAccessError",
+        }
+    }
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Error for AudioError {}
@@ -1,11 +1,12 @@
 use block::Chunk;
-use block::Tick;
+use block::{Block, Tick};
+use error::{AudioError, AudioResult};
 use node::AudioNodeEngine;
-use node::BlockInfo;
+use node::{AudioNodeInit, BlockInfo};
 use node::{AudioNodeType, ChannelInfo};
 use param::{Param, ParamType};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct GainNodeOptions {
     pub gain: f32,
 }
@@ -40,6 +41,12 @@ impl AudioNodeEngine for GainNode {
         AudioNodeType::GainNode
     }
 
+    fn init_options(&self) -> AudioNodeInit {
+        AudioNodeInit::GainNode(GainNodeOptions {
+            gain: self.gain.value(),
+        })
+    }
+
     fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 1);
 
@@ -47,24 +54,28 @@ impl AudioNodeEngine for GainNode {
             return inputs;
         }
 
-        {
-            let mut iter = inputs.blocks[0].iter();
-            let mut gain = self.gain.value();
-
-            while let Some(mut frame) = iter.next() {
-                if self.update_parameters(info, frame.tick()) {
-                    gain = self.gain.value();
-                }
-                frame.mutate_with(|sample| *sample = *sample * gain);
+        let mut gains = Block::new(info.frames);
+        let gains = gains.data_mut();
+        let mut gain = self.gain.value();
+        for (frame, g) in gains.iter_mut().enumerate() {
+            if self.update_parameters(info, Tick(frame as u64)) {
+                gain = self.gain.value();
             }
+            *g = gain;
+        }
+
+        if gains.iter().all(|g| *g == gains[0]) {
+            inputs.blocks[0].scale(gains[0]);
+        } else {
+            inputs.blocks[0].scale_by(gains);
         }
         inputs
     }
 
-    fn get_param(&mut self, id: ParamType) -> &mut Param {
+    fn get_param(&mut self, id: ParamType) -> AudioResult<&mut Param> {
         match id {
-            ParamType::Gain => &mut self.gain,
-            _ => panic!("Unknown param {:?} for GainNode", id),
+            ParamType::Gain => Ok(&mut self.gain),
+            _ => Err(AudioError::InvalidAccess),
         }
     }
 }
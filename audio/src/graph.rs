@@ -1,15 +1,25 @@
 use param::ParamType;
-use block::{Block, Chunk};
+use block::{Block, Chunk, Tick};
+use channel_layout::MixingMatrix;
 use destination_node::DestinationNode;
+use error::{AudioError, AudioResult};
 use node::{AudioNodeEngine, BlockInfo, ChannelCountMode, ChannelInterpretation};
 use petgraph::graph::DefaultIx;
-use petgraph::stable_graph::NodeIndex;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
 use petgraph::stable_graph::StableGraph;
-use petgraph::visit::{DfsPostOrder, EdgeRef, Reversed};
+use petgraph::visit::{DfsPostOrder, EdgeRef, IntoEdgeReferences, NodeIndexable, Reversed};
 use petgraph::Direction;
+use pool::SharedPool;
+use preset::{ConnectionPreset, GraphPreset, NodePreset};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use smallvec::SmallVec;
-use std::cell::{RefCell, RefMut};
+use snapshot::{ConnectionSnapshot, GraphSnapshot, NodeSnapshot};
+use std::cell::{Cell, RefCell, RefMut};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{cmp, fmt, hash};
+use worker_pool::WorkerPool;
 
 #[derive(Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Debug)]
 /// A unique identifier for nodes in the graph. Stable
@@ -17,6 +27,11 @@ use std::{cmp, fmt, hash};
 pub struct NodeId(NodeIndex<DefaultIx>);
 
 impl NodeId {
+    /// A number identifying the node, e.g. for labelling it
+    pub fn index(self) -> usize {
+        (self.0).index()
+    }
+
     pub fn input(self, port: u32) -> PortId<InputPort> {
         PortId(self, PortIndex::Port(port))
     }
@@ -26,6 +41,23 @@ impl NodeId {
     pub fn output(self, port: u32) -> PortId<OutputPort> {
         PortId(self, PortIndex::Port(port))
     }
+    pub fn port<Kind: PortKind>(self, index: PortIndex<Kind>) -> PortId<Kind> {
+        PortId(self, index)
+    }
+}
+
+// Ids are only meaningful within the graph they come from, so they are
+// stored as plain numbers, e.g. for referring to nodes within a preset
+impl Serialize for NodeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.index() as u32)
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u32::deserialize(deserializer).map(|index| NodeId(NodeIndex::new(index as usize)))
+    }
 }
 
 /// A zero-indexed "port" for a node. Most nodes have one
@@ -37,7 +69,11 @@ impl NodeId {
 ///
 /// Kind is a zero sized type and is useful for distinguishing
 /// between input and output ports (which may otherwise share indices)
-#[derive(Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "Kind::ParamId: Serialize",
+    deserialize = "Kind::ParamId: Deserialize<'de>"
+))]
 pub enum PortIndex<Kind: PortKind> {
     Port(u32),
     Param(Kind::ParamId)
@@ -49,6 +85,12 @@ impl<Kind: PortKind> PortId<Kind> {
     }
 }
 
+impl<Kind: PortKind + Copy> PortId<Kind> {
+    pub fn index(&self) -> PortIndex<Kind> {
+        self.1
+    }
+}
+
 pub trait PortKind {
     type ParamId: Copy + Eq + PartialEq + Ord
                        + PartialOrd + hash::Hash + fmt::Debug;
@@ -83,10 +125,131 @@ impl PortKind for OutputPort {
 pub struct AudioGraph {
     graph: StableGraph<Node, Edge>,
     dest_id: NodeId,
+    /// The order in which nodes are processed, computed lazily
+    /// and invalidated whenever the graph is mutated
+    schedule: Option<Schedule>,
+    scratch: Scratch,
+    /// The gains of connections fading in or out over a block
+    ramps: FadeRamps,
+    /// Threads processing independent nodes in parallel, if enabled
+    workers: Option<WorkerPool>,
+    /// Whether there are connections to fade out, see `disconnect_with_fade`
+    fading_out: bool,
+    /// Whether to time the processing of each node
+    profiling: bool,
+    /// Number of render quanta left for the pool to grow in, see `alloc_check`
+    #[cfg(feature = "alloc_check")]
+    warm_up: u32,
+}
+
+/// Check that `matrix` can mix the inputs of `engine`,
+/// see `AudioGraph::set_mixing_matrix`
+pub(crate) fn check_mixing_matrix(
+    engine: &AudioNodeEngine,
+    matrix: &MixingMatrix,
+) -> AudioResult<()> {
+    matrix.check()?;
+    if engine.channel_count_mode() == ChannelCountMode::Explicit
+        && engine.channel_count() != matrix.outputs()
+    {
+        return Err(AudioError::NotSupported);
+    }
+    Ok(())
+}
+
+/// Scratch space for processing a node, kept around so that
+/// processing does not need to allocate
+#[derive(Default)]
+pub(crate) struct Scratch {
+    /// The blocks sent to each input port
+    port_blocks: Vec<SmallVec<[Block; 1]>>,
+    /// The consumers left for each output port
+    output_counts: Vec<u32>,
+    /// The edges of the graph, while going over them
+    edges: Vec<EdgeIndex>,
+}
+
+impl Scratch {
+    /// Make room for processing any node of the schedule, since nodes
+    /// that are not actively processing may only need it later on
+    pub(crate) fn reserve(&mut self, schedule: &Schedule) {
+        for node in &schedule.nodes {
+            let inputs = node.input_counts.len();
+            if self.port_blocks.len() < inputs {
+                self.port_blocks.resize(inputs, SmallVec::new());
+            }
+            for (blocks, count) in self.port_blocks.iter_mut().zip(&node.input_counts) {
+                blocks.reserve(*count as usize);
+            }
+            self.output_counts.clear();
+            self.output_counts.reserve(node.output_counts.len());
+        }
+    }
+}
+
+/// The processing order of the graph.
+///
+/// Nodes are grouped into levels, a node only depending on nodes of
+/// earlier levels. Nodes within a level can thus be processed in any
+/// order, or concurrently. Nodes that do not lead to the destination
+/// are not scheduled.
+struct Schedule {
+    nodes: Vec<ScheduledNode>,
+    /// The range of `nodes` each level spans
+    levels: Vec<Range<usize>>,
+}
+
+/// A node in the schedule, along with its connection counts
+struct ScheduledNode {
+    id: NodeIndex<DefaultIx>,
+    /// Number of connections feeding into each input port
+    input_counts: SmallVec<[u32; 1]>,
+    /// Number of connections fanning out from each output port
+    ///
+    /// This is so that we don't have to needlessly clone audio buffers,
+    /// the last consumer of a port can take its data instead
+    output_counts: SmallVec<[u32; 1]>,
 }
 
 pub(crate) struct Node {
     node: RefCell<Box<AudioNodeEngine>>,
+    /// The node can be skipped once it reaches this tick with silent
+    /// inputs, see `AudioNodeEngine::tail_time`
+    tail_end: Cell<Tick>,
+    /// Time spent processing the node, when profiling
+    profile: Cell<NodeProfile>,
+    /// Mixes the inputs it applies to, see `AudioGraph::set_mixing_matrix`
+    mixing: Option<MixingMatrix>,
+}
+
+/// Time spent in the `process` method of a node, see
+/// `AudioGraph::set_profiling`. Skipped blocks are not counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NodeProfile {
+    /// Total time spent processing the node
+    pub total: Duration,
+    /// Longest time spent processing a single block
+    pub max: Duration,
+    /// Number of blocks processed
+    pub blocks: u64,
+}
+
+impl NodeProfile {
+    fn record(&mut self, elapsed: Duration) {
+        self.total += elapsed;
+        if elapsed > self.max {
+            self.max = elapsed;
+        }
+        self.blocks += 1;
+    }
+
+    /// Average time spent processing a block
+    pub fn average(&self) -> Duration {
+        if self.blocks == 0 {
+            return Duration::default();
+        }
+        self.total / self.blocks as u32
+    }
 }
 
 /// An edge in the graph
@@ -101,16 +264,15 @@ pub(crate) struct Edge {
 }
 
 impl Edge {
-    /// Find if there are connections between two given ports, return the index
-    fn has_between(
+    /// Find the connection between two given ports, if any
+    fn find_between(
         &self,
         output_idx: PortIndex<OutputPort>,
         input_idx: PortIndex<InputPort>,
-    ) -> bool {
+    ) -> Option<&Connection> {
         self.connections
             .iter()
             .find(|e| e.input_idx == input_idx && e.output_idx == output_idx)
-            .is_some()
     }
 
     fn remove_by_output(&mut self, output_idx: PortIndex<OutputPort>) {
@@ -131,6 +293,76 @@ impl Edge {
     }
 }
 
+/// Which connections to remove, as with the `disconnect_*` methods
+/// of `AudioGraph` of the same name
+#[derive(Clone, Copy, Debug)]
+pub enum Disconnection {
+    AllFrom(NodeId),
+    Output(PortId<OutputPort>),
+    Between(NodeId, NodeId),
+    To(NodeId, PortId<InputPort>),
+    OutputBetween(PortId<OutputPort>, NodeId),
+    OutputBetweenTo(PortId<OutputPort>, PortId<InputPort>),
+}
+
+impl Disconnection {
+    /// The node the connections go out of
+    fn from(&self) -> NodeId {
+        match *self {
+            Disconnection::AllFrom(node)
+            | Disconnection::Between(node, _)
+            | Disconnection::To(node, _) => node,
+            Disconnection::Output(out)
+            | Disconnection::OutputBetween(out, _)
+            | Disconnection::OutputBetweenTo(out, _) => out.node(),
+        }
+    }
+
+    /// Whether a connection out of `from()` into `to` is to be removed
+    fn matches(&self, to: NodeId, conn: &Connection) -> bool {
+        match *self {
+            Disconnection::AllFrom(_) => true,
+            Disconnection::Output(out) => conn.output_idx == out.1,
+            Disconnection::Between(_, node) => to == node,
+            Disconnection::To(_, inp) => to == inp.0 && conn.input_idx == inp.1,
+            Disconnection::OutputBetween(out, node) => to == node && conn.output_idx == out.1,
+            Disconnection::OutputBetweenTo(out, inp) => {
+                to == inp.0 && conn.output_idx == out.1 && conn.input_idx == inp.1
+            }
+        }
+    }
+}
+
+/// A crossfade a connection is going through
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Fade {
+    None,
+    /// The connection was just made, and fades in over the next block
+    In,
+    /// The connection is to fade out, starting with the next block
+    Out,
+    /// The connection fades out over this block, to be removed after
+    FadingOut,
+}
+
+/// The gains applied to a block going through a connection that
+/// fades in or out, computed once for the render quantum size
+#[derive(Default)]
+struct FadeRamps {
+    fade_in: Vec<f32>,
+    fade_out: Vec<f32>,
+}
+
+impl FadeRamps {
+    fn new(frames: usize) -> Self {
+        let fade_in: Vec<f32> = (0..frames)
+            .map(|frame| (frame as f32 + 0.5) / frames as f32)
+            .collect();
+        let fade_out = fade_in.iter().map(|gain| 1. - gain).collect();
+        FadeRamps { fade_in, fade_out }
+    }
+}
+
 /// A single connection between ports
 struct Connection {
     /// The index of the port on the input node
@@ -142,37 +374,149 @@ struct Connection {
     /// When the from node finishes processing, it will push
     /// its data into this cache for the input node to read
     cache: RefCell<Option<Block>>,
+    fade: Cell<Fade>,
 }
 
 impl AudioGraph {
     pub fn new() -> Self {
         let mut graph = StableGraph::new();
         let dest_id = NodeId(graph.add_node(Node::new(Box::new(DestinationNode::new()))));
-        AudioGraph { graph, dest_id }
+        AudioGraph {
+            graph,
+            dest_id,
+            schedule: None,
+            scratch: Scratch::default(),
+            ramps: FadeRamps::default(),
+            workers: None,
+            fading_out: false,
+            profiling: false,
+            #[cfg(feature = "alloc_check")]
+            warm_up: 0,
+        }
+    }
+
+    /// Set the number of threads used for processing the graph,
+    /// including the thread calling `process`.
+    ///
+    /// With more than one thread, nodes that do not depend on each other
+    /// are processed in parallel. Each node still sees exactly the same
+    /// inputs in the same order, so the output is identical to that of
+    /// processing the graph on a single thread.
+    pub fn set_render_threads(&mut self, threads: usize) {
+        self.spawn_workers(threads);
+        self.join_workers();
+    }
+
+    /// Like `set_render_threads`, but for a graph processed by another
+    /// thread than the calling one, which needs to `join_workers` first
+    pub(crate) fn spawn_workers(&mut self, threads: usize) {
+        // Shut down the current workers before starting new ones,
+        // which makes the shared buffer pool change too
+        self.workers = None;
+        if threads > 1 {
+            let buffers = Arc::new(SharedPool::default());
+            self.workers = Some(WorkerPool::new(threads - 1, buffers));
+            // the scratch space of the new workers needs to be reserved
+            self.schedule = None;
+        }
+    }
+
+    /// Make the calling thread exchange block buffers with the workers,
+    /// if any. This does not allocate.
+    pub(crate) fn join_workers(&self) {
+        if let Some(ref workers) = self.workers {
+            workers.join();
+        }
+    }
+
+    /// Start or stop timing how long each node takes to process.
+    /// The timings gathered so far are reset either way.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiling = profiling;
+        for ix in self.graph.node_indices() {
+            self.graph[ix].profile.set(NodeProfile::default());
+        }
+    }
+
+    /// The time spent processing a node since profiling started
+    pub fn node_profile(&self, node: NodeId) -> AudioResult<NodeProfile> {
+        self.graph
+            .node_weight(node.0)
+            .map(|n| n.profile.get())
+            .ok_or(AudioError::InvalidAccess)
+    }
+
+    /// Mix the input blocks of a node that have as many channels as the
+    /// matrix has inputs through the matrix, or stop doing so if `None`.
+    ///
+    /// This happens before the usual mixing to the channel count of the
+    /// node, which still applies to the output of the matrix. Input blocks
+    /// with another channel count skip the matrix, since the channel count
+    /// of an input is only known while rendering.
+    ///
+    /// Fails with `InvalidAccess` for unknown nodes, and `NotSupported`
+    /// for a node with an explicit channel count the matrix does not
+    /// output. Matrices that did not go through the constructors are
+    /// checked first.
+    pub fn set_mixing_matrix(
+        &mut self,
+        node: NodeId,
+        matrix: Option<MixingMatrix>,
+    ) -> AudioResult<()> {
+        let node = self
+            .graph
+            .node_weight_mut(node.0)
+            .ok_or(AudioError::InvalidAccess)?;
+        if let Some(ref matrix) = matrix {
+            check_mixing_matrix(&**node.node.borrow(), matrix)?;
+        }
+        node.mixing = matrix;
+        Ok(())
+    }
+
+    pub fn mixing_matrix(&self, node: NodeId) -> AudioResult<Option<&MixingMatrix>> {
+        self.graph
+            .node_weight(node.0)
+            .map(|n| n.mixing.as_ref())
+            .ok_or(AudioError::InvalidAccess)
     }
 
     /// Create a node, obtain its id
     pub(crate) fn add_node(&mut self, node: Box<AudioNodeEngine>) -> NodeId {
+        self.schedule = None;
         NodeId(self.graph.add_node(Node::new(node)))
     }
 
     /// Connect an output port to an input port
     ///
     /// The edge goes *from* the output port *to* the input port, connecting two nodes
-    pub fn add_edge(&mut self, out: PortId<OutputPort>, inp: PortId<InputPort>) {
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audionode-connect
+    pub fn add_edge(
+        &mut self,
+        out: PortId<OutputPort>,
+        inp: PortId<InputPort>,
+    ) -> AudioResult<()> {
+        self.check_ports(out, inp)?;
+        self.schedule = None;
         let edge = self
             .graph
             .edges(out.node().0)
             .find(|e| e.target() == inp.node().0)
             .map(|e| e.id());
         if let Some(e) = edge {
-            // .find(|e| e.weight().has_between(out.1, inp.1));
             let w = self
                 .graph
                 .edge_weight_mut(e)
                 .expect("This edge is known to exist");
-            if w.has_between(out.1, inp.1) {
-                return;
+            if let Some(conn) = w.find_between(out.1, inp.1) {
+                // connecting again cancels a disconnection that
+                // has yet to take effect
+                match conn.fade.get() {
+                    Fade::Out | Fade::FadingOut => conn.fade.set(Fade::None),
+                    Fade::None | Fade::In => {}
+                }
+                return Ok(());
             }
             w.connections.push(Connection::new(inp.1, out.1))
         } else {
@@ -180,12 +524,126 @@ impl AudioGraph {
             self.graph
                 .add_edge(out.node().0, inp.node().0, Edge::new(inp.1, out.1));
         }
+        Ok(())
+    }
+
+    /// Connect an output port to an input port like `add_edge`, fading
+    /// the connection in over the next block instead of all at once
+    pub fn add_edge_with_fade(
+        &mut self,
+        out: PortId<OutputPort>,
+        inp: PortId<InputPort>,
+    ) -> AudioResult<()> {
+        let mut fade = None;
+        self.for_each_connection(out.node(), |to, conn| {
+            if to == inp.0 && conn.output_idx == out.1 && conn.input_idx == inp.1 {
+                fade = Some(conn.fade.get());
+            }
+        });
+        self.add_edge(out, inp)?;
+        match fade {
+            // `add_edge` has cancelled a fade out that has yet to start,
+            // any other existing connection is left as it is
+            Some(Fade::Out) | Some(Fade::None) | Some(Fade::In) => {}
+            // new, or silent since fading out over the last block
+            None | Some(Fade::FadingOut) => {
+                self.for_each_connection(out.node(), |to, conn| {
+                    if to == inp.0 && conn.output_idx == out.1 && conn.input_idx == inp.1 {
+                        conn.fade.set(Fade::In);
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove connections like the `disconnect_*` methods, fading them
+    /// out over the next block instead of cutting them off
+    pub fn disconnect_with_fade(&mut self, disconnection: Disconnection) {
+        let mut any = false;
+        self.for_each_connection(disconnection.from(), |to, conn| {
+            if disconnection.matches(to, conn) {
+                conn.fade.set(Fade::Out);
+                any = true;
+            }
+        });
+        if any {
+            self.fading_out = true;
+        }
+    }
+
+    fn for_each_connection<F: FnMut(NodeId, &Connection)>(&self, from: NodeId, mut f: F) {
+        for edge in self.graph.edges(from.0) {
+            for conn in &edge.weight().connections {
+                f(NodeId(edge.target()), conn);
+            }
+        }
+    }
+
+    /// Remove the connections that faded out over the last block, and
+    /// start fading out the ones that were disconnected since.
+    /// Returns whether any connections are still fading out.
+    fn advance_fade_outs(&mut self) -> bool {
+        let mut fading = false;
+        let AudioGraph {
+            ref mut graph,
+            ref mut scratch,
+            ref mut schedule,
+            ..
+        } = *self;
+        scratch.edges.clear();
+        scratch.edges.extend(graph.edge_indices());
+        for &edge in &scratch.edges {
+            let (removed, empty) = {
+                let weight = &mut graph[edge];
+                let before = weight.connections.len();
+                weight
+                    .connections
+                    .retain(|conn| conn.fade.get() != Fade::FadingOut);
+                for conn in &weight.connections {
+                    if conn.fade.get() == Fade::Out {
+                        conn.fade.set(Fade::FadingOut);
+                        fading = true;
+                    }
+                }
+                (weight.connections.len() != before, weight.connections.is_empty())
+            };
+            if removed {
+                *schedule = None;
+            }
+            if empty {
+                graph.remove_edge(edge);
+            }
+        }
+        fading
+    }
+
+    /// Check that both ends of a connection exist on their nodes
+    pub(crate) fn check_ports(&self, out: PortId<OutputPort>, inp: PortId<InputPort>) -> AudioResult<()> {
+        if let PortIndex::Port(idx) = out.1 {
+            if idx >= self.node_mut(out.node())?.output_count() {
+                return Err(AudioError::IndexSize);
+            }
+        }
+        let mut to = self.node_mut(inp.node())?;
+        match inp.1 {
+            PortIndex::Port(idx) => {
+                if idx >= to.input_count() {
+                    return Err(AudioError::IndexSize);
+                }
+            }
+            PortIndex::Param(param) => {
+                to.get_param(param)?;
+            }
+        }
+        Ok(())
     }
 
     /// Disconnect all outgoing connections from a node
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect
     pub fn disconnect_all_from(&mut self, node: NodeId) {
+        self.schedule = None;
         let edges = self.graph.edges(node.0).map(|e| e.id()).collect::<Vec<_>>();
         for edge in edges {
             self.graph.remove_edge(edge);
@@ -196,6 +654,7 @@ impl AudioGraph {
     // ///
     // /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-output
     pub fn disconnect_output(&mut self, out: PortId<OutputPort>) {
+        self.schedule = None;
         let candidates: Vec<_> = self
             .graph
             .edges(out.node().0)
@@ -217,6 +676,7 @@ impl AudioGraph {
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationnode
     pub fn disconnect_between(&mut self, from: NodeId, to: NodeId) {
+        self.schedule = None;
         let edge = self
             .graph
             .edges(from.0)
@@ -231,6 +691,7 @@ impl AudioGraph {
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationnode-output
     pub fn disconnect_output_between(&mut self, out: PortId<OutputPort>, to: NodeId) {
+        self.schedule = None;
         let edge = self
             .graph
             .edges(out.node().0)
@@ -258,6 +719,7 @@ impl AudioGraph {
         node: NodeId,
         inp: PortId<InputPort>,
     ) {
+        self.schedule = None;
         let edge = self
             .graph
             .edges(node.0)
@@ -284,6 +746,7 @@ impl AudioGraph {
         out: PortId<OutputPort>,
         inp: PortId<InputPort>,
     ) {
+        self.schedule = None;
         let edge = self
             .graph
             .edges(out.node().0)
@@ -310,160 +773,485 @@ impl AudioGraph {
 
     /// For a given block, process all the data on this graph
     pub fn process(&mut self, info: &BlockInfo) -> Chunk {
-        // DFS post order: Children are processed before their parent,
-        // which is exactly what we need since the parent depends on the
-        // children's output
-        //
-        // This will only visit each node once
-        let reversed = Reversed(&self.graph);
-        let mut visit = DfsPostOrder::new(reversed, self.dest_id.0);
-
-        let mut blocks: SmallVec<[SmallVec<[Block; 1]>; 1]> = SmallVec::new();
-        let mut output_counts: SmallVec<[u32; 1]> = SmallVec::new();
+        if self.ramps.fade_in.len() != info.frames {
+            self.ramps = FadeRamps::new(info.frames);
+        }
+        if self.fading_out {
+            self.fading_out = self.advance_fade_outs();
+        }
 
-        while let Some(ix) = visit.next(reversed) {
-            let mut curr = self.graph[ix].node.borrow_mut();
+        let rescheduled = self.schedule.is_none();
+        if rescheduled {
+            let schedule = Schedule::new(&self.graph, self.dest_id);
+            self.scratch.reserve(&schedule);
+            if let Some(ref workers) = self.workers {
+                workers.reserve_scratch(|scratch| scratch.reserve(&schedule));
+            }
+            // edges are only added along with a new schedule, so
+            // going over them does not allocate in between
+            self.scratch.edges.reserve(self.graph.edge_count());
+            self.schedule = Some(schedule);
+        }
 
-            let mut chunk = Chunk::default();
+        #[cfg(feature = "alloc_check")]
+        {
+            if rescheduled {
+                self.warm_up = ::alloc_check::WARM_UP_QUANTA;
+            }
+            let warming_up = self.warm_up > 0;
+            self.warm_up = self.warm_up.saturating_sub(1);
+            let (chunk, allocations) = ::alloc_check::count_allocations(|| {
+                if warming_up {
+                    ::alloc_check::warm_up(|| self.process_schedule(info))
+                } else {
+                    self.process_schedule(info)
+                }
+            });
+            // After the graph changes, the first blocks may need to grow
+            // the pool to fit the new schedule. From then on we should
+            // not be touching the allocator, not even through the pool.
+            assert!(
+                warming_up || allocations == 0,
+                "AudioGraph::process made {} allocations",
+                allocations
+            );
             chunk
-                .blocks
-                .resize(curr.input_count() as usize, Default::default());
-
-            // if we have inputs, collect all the computed blocks
-            // and construct a Chunk
-
-            // set up scratch space to store all the blocks
-            blocks.clear();
-            blocks.resize(curr.input_count() as usize, Default::default());
-
-            let mode = curr.channel_count_mode();
-            let count = curr.channel_count();
-            let interpretation = curr.channel_interpretation();
-
-            // all edges to this node are from its dependencies
-            for edge in self.graph.edges_directed(ix, Direction::Incoming) {
-                let edge = edge.weight();
-                for connection in &edge.connections {
-                    let mut block = connection
-                        .cache
-                        .borrow_mut()
-                        .take()
-                        .expect("Cache should have been filled from traversal");
-
-                    match connection.input_idx {
-                        PortIndex::Port(idx) => {
-                            blocks[idx as usize].push(block);
-                        }
-                        PortIndex::Param(param) => {
-                            // param inputs are downmixed to mono
-                            // https://webaudio.github.io/web-audio-api/#dom-audionode-connect-destinationparam-output
-                            block.mix(1, ChannelInterpretation::Speakers);
-                            curr.get_param(param).add_block(block)
+        }
+
+        #[cfg(not(feature = "alloc_check"))]
+        self.process_schedule(info)
+    }
+
+    fn process_schedule(&mut self, info: &BlockInfo) -> Chunk {
+        let AudioGraph {
+            ref graph,
+            ref schedule,
+            ref mut scratch,
+            ref ramps,
+            ref mut workers,
+            dest_id,
+            profiling,
+            ..
+        } = *self;
+        let schedule = schedule.as_ref().expect("Schedule should have been computed");
+
+        if let Some(ref mut workers) = *workers {
+            let shared = SharedGraph(graph);
+            for level in &schedule.levels {
+                let nodes = &schedule.nodes[level.clone()];
+                if nodes.len() == 1 {
+                    // not worth waking up the workers
+                    process_node(graph, &nodes[0], info, ramps, scratch, profiling);
+                    continue;
+                }
+                workers.run(nodes.len(), scratch, &|i, scratch| {
+                    process_node(shared.0, &nodes[i], info, ramps, scratch, profiling)
+                });
+            }
+        } else {
+            for scheduled in &schedule.nodes {
+                process_node(graph, scheduled, info, ramps, scratch, profiling);
+            }
+        }
+
+        // The destination node stores its output on itself, extract it.
+        graph[dest_id.0]
+            .node
+            .borrow_mut()
+            .destination_data()
+            .expect("Destination node should have data cached")
+    }
+
+    /// Take a snapshot of the nodes of this graph and
+    /// the connections between them
+    pub fn snapshot(&self) -> GraphSnapshot {
+        let nodes = self
+            .graph
+            .node_indices()
+            .map(|ix| {
+                let mut node = self.graph[ix].node.borrow_mut();
+                let params = ParamType::ALL
+                    .iter()
+                    .filter_map(|&id| node.get_param(id).ok().map(|p| (id, p.value())))
+                    .collect();
+                NodeSnapshot {
+                    id: NodeId(ix),
+                    node_type: node.node_type(),
+                    channel_info: *node.channel_info(),
+                    input_count: node.input_count(),
+                    output_count: node.output_count(),
+                    params,
+                }
+            })
+            .collect();
+
+        let mut connections = Vec::new();
+        for edge in self.graph.edge_references() {
+            let (from, to) = (NodeId(edge.source()), NodeId(edge.target()));
+            for conn in &edge.weight().connections {
+                connections.push(ConnectionSnapshot {
+                    output: PortId(from, conn.output_idx),
+                    input: PortId(to, conn.input_idx),
+                });
+            }
+        }
+
+        GraphSnapshot {
+            destination: self.dest_id,
+            nodes,
+            connections,
+        }
+    }
+
+    /// Save the setup of this graph, see `GraphPreset`
+    pub fn save_preset(&self, sample_rate: f32) -> GraphPreset {
+        let nodes = self
+            .graph
+            .node_indices()
+            .map(|ix| {
+                let mut node = self.graph[ix].node.borrow_mut();
+                let params = ParamType::ALL
+                    .iter()
+                    .filter_map(|&id| {
+                        node.get_param(id)
+                            .ok()
+                            .map(|p| p.to_preset(id, sample_rate))
+                    })
+                    .collect();
+                NodePreset {
+                    id: NodeId(ix),
+                    init: node.init_options(),
+                    channel_info: *node.channel_info(),
+                    mixing: self.graph[ix].mixing.clone(),
+                    params,
+                }
+            })
+            .collect();
+
+        let mut connections = Vec::new();
+        for edge in self.graph.edge_references() {
+            let (from, to) = (NodeId(edge.source()), NodeId(edge.target()));
+            for conn in &edge.weight().connections {
+                if let PortIndex::Port(output) = conn.output_idx {
+                    connections.push(ConnectionPreset {
+                        from,
+                        output,
+                        to,
+                        input: conn.input_idx,
+                    });
+                }
+            }
+        }
+
+        GraphPreset { nodes, connections }
+    }
+
+    /// Render this graph in the Graphviz DOT language
+    pub fn to_dot(&self) -> String {
+        self.snapshot().to_dot()
+    }
+
+    /// Describe this graph as JSON, see `GraphSnapshot::to_json`
+    pub fn to_json(&self) -> String {
+        self.snapshot().to_json()
+    }
+
+    /// Obtain a mutable reference to a node, failing if it
+    /// does not belong to this graph
+    pub(crate) fn node_mut(&self, ix: NodeId) -> AudioResult<RefMut<Box<AudioNodeEngine>>> {
+        self.graph
+            .node_weight(ix.0)
+            .map(|n| n.node.borrow_mut())
+            .ok_or(AudioError::InvalidAccess)
+    }
+}
+
+/// Wrapper letting the graph be shared with the worker threads.
+///
+/// The graph is not Sync, because of the cells of its nodes and
+/// connections. Processing a node only touches the node itself and the
+/// connections going into and out of it, none of which are touched by the
+/// other nodes of its level. Levels are separated by the worker pool
+/// synchronizing with the render thread.
+struct SharedGraph<'a>(&'a StableGraph<Node, Edge>);
+
+unsafe impl<'a> Sync for SharedGraph<'a> {}
+
+/// Whether any param of a node has automation left to run, which has
+/// to carry on even while the node only outputs silence
+fn has_automation(node: &mut AudioNodeEngine) -> bool {
+    ParamType::ALL
+        .iter()
+        .any(|&param| node.get_param(param).map_or(false, |param| param.has_events()))
+}
+
+/// Mix the inputs of a node, process it, and pass its outputs
+/// on to the connections leading out of it
+fn process_node(
+    graph: &StableGraph<Node, Edge>,
+    scheduled: &ScheduledNode,
+    info: &BlockInfo,
+    ramps: &FadeRamps,
+    scratch: &mut Scratch,
+    profiling: bool,
+) {
+    let Scratch {
+        ref mut port_blocks,
+        ref mut output_counts,
+        ..
+    } = *scratch;
+    let ix = scheduled.id;
+    let node = &graph[ix];
+    let mut curr = node.node.borrow_mut();
+
+    let has_input = graph
+        .edges_directed(ix, Direction::Incoming)
+        .flat_map(|edge| edge.weight().connections.iter())
+        .any(|conn| {
+            conn.cache
+                .borrow()
+                .as_ref()
+                .map_or(false, |block| !block.is_silence())
+        });
+    if has_input {
+        node.tail_end
+            .set(info.frame + info.frames as u64 + curr.tail_time());
+    } else if info.frame >= node.tail_end.get()
+        && !curr.is_active(info)
+        && !has_automation(&mut **curr)
+    {
+        // The node would only output silence, don't bother processing it
+        for edge in graph.edges_directed(ix, Direction::Incoming) {
+            for conn in &edge.weight().connections {
+                conn.cache.borrow_mut().take();
+                // fading in silence is a no-op, so the fade is done
+                if conn.fade.get() == Fade::In {
+                    conn.fade.set(Fade::None);
+                }
+            }
+        }
+        for edge in graph.edges(ix) {
+            for conn in &edge.weight().connections {
+                *conn.cache.borrow_mut() = Some(Block::new(info.frames));
+            }
+        }
+        return;
+    }
+
+    let input_count = scheduled.input_counts.len();
+    let mut chunk = Chunk::default();
+    chunk.blocks.resize(input_count, Block::new(info.frames));
+
+    // if we have inputs, collect all the computed blocks
+    // and construct a Chunk
+
+    // set up scratch space to store all the blocks
+    if port_blocks.len() < input_count {
+        port_blocks.resize(input_count, SmallVec::new());
+    }
+    let blocks = &mut port_blocks[..input_count];
+
+    let mode = curr.channel_count_mode();
+    let count = curr.channel_count();
+    let interpretation = curr.channel_interpretation();
+
+    // all edges to this node are from its dependencies
+    for edge in graph.edges_directed(ix, Direction::Incoming) {
+        let edge = edge.weight();
+        for connection in &edge.connections {
+            let mut block = connection
+                .cache
+                .borrow_mut()
+                .take()
+                .expect("Cache should have been filled from traversal");
+            connection.apply_fade(&mut block, ramps);
+
+            match connection.input_idx {
+                PortIndex::Port(idx) => {
+                    if let Some(ref matrix) = node.mixing {
+                        if block.chan_count() == matrix.inputs() {
+                            block.mix_matrix(matrix);
                         }
                     }
+                    blocks[idx as usize].push(block);
+                }
+                PortIndex::Param(param) => {
+                    // param inputs are downmixed to mono
+                    // https://webaudio.github.io/web-audio-api/#dom-audionode-connect-destinationparam-output
+                    block.mix(1, ChannelInterpretation::Speakers);
+                    // connections to params are validated in add_edge()
+                    if let Ok(param) = curr.get_param(param) {
+                        param.add_block(block)
+                    }
                 }
             }
+        }
+    }
 
-            for (i, mut blocks) in blocks.drain().enumerate() {
-                if blocks.len() == 0 {
-                    if mode == ChannelCountMode::Explicit {
-                        // It's silence, but mix it anyway
+    for (i, blocks) in blocks.iter_mut().enumerate() {
+        if blocks.len() == 0 {
+            if mode == ChannelCountMode::Explicit {
+                // It's silence, but mix it anyway
+                chunk.blocks[i].mix(count, interpretation);
+            }
+        } else if blocks.len() == 1 {
+            chunk.blocks[i] = blocks.pop().expect("`blocks` had length 1");
+            match mode {
+                ChannelCountMode::Explicit => {
+                    chunk.blocks[i].mix(count, interpretation);
+                }
+                ChannelCountMode::ClampedMax => {
+                    if chunk.blocks[i].chan_count() > count {
                         chunk.blocks[i].mix(count, interpretation);
                     }
-                } else if blocks.len() == 1 {
-                    chunk.blocks[i] = blocks.pop().expect("`blocks` had length 1");
-                    match mode {
-                        ChannelCountMode::Explicit => {
-                            chunk.blocks[i].mix(count, interpretation);
-                        }
-                        ChannelCountMode::ClampedMax => {
-                            if chunk.blocks[i].chan_count() > count {
-                                chunk.blocks[i].mix(count, interpretation);
-                            }
-                        }
-                        // It's one channel, it maxes itself
-                        ChannelCountMode::Max => (),
-                    }
-                } else {
-                    let mix_count = match mode {
-                        ChannelCountMode::Explicit => count,
-                        _ => {
-                            let mut max = 0; // max channel count
-                            for block in &blocks {
-                                max = cmp::max(max, block.chan_count());
-                            }
-                            if mode == ChannelCountMode::ClampedMax {
-                                max = cmp::min(max, count);
-                            }
-                            max
-                        }
-                    };
-                    let block = blocks.into_iter().fold(Block::default(), |acc, mut block| {
-                        block.mix(mix_count, interpretation);
-                        acc.sum(block)
-                    });
-                    chunk.blocks[i] = block;
                 }
+                // It's one channel, it maxes itself
+                ChannelCountMode::Max => (),
             }
+        } else {
+            let mix_count = match mode {
+                ChannelCountMode::Explicit => count,
+                _ => {
+                    let mut max = 0; // max channel count
+                    for block in blocks.iter() {
+                        max = cmp::max(max, block.chan_count());
+                    }
+                    if mode == ChannelCountMode::ClampedMax {
+                        max = cmp::min(max, count);
+                    }
+                    max
+                }
+            };
+            let block = blocks.drain().fold(Block::new(info.frames), |acc, mut block| {
+                block.mix(mix_count, interpretation);
+                acc.sum(block)
+            });
+            chunk.blocks[i] = block;
+        }
+    }
+
+    // actually run the node engine
+    let mut out = if profiling {
+        let start = Instant::now();
+        let out = curr.process(chunk, info);
+        let mut profile = node.profile.get();
+        profile.record(start.elapsed());
+        node.profile.set(profile);
+        out
+    } else {
+        curr.process(chunk, info)
+    };
+
+    assert_eq!(out.len(), curr.output_count() as usize);
+    if curr.output_count() == 0 {
+        return;
+    }
 
-            // actually run the node engine
-            let mut out = curr.process(chunk, info);
+    // Keep track of how many consumers of each port are left
+    output_counts.clear();
+    output_counts.extend_from_slice(&scheduled.output_counts);
 
-            assert_eq!(out.len(), curr.output_count() as usize);
-            if curr.output_count() == 0 {
-                continue;
+    // all the edges from this node go to nodes which depend on it,
+    // i.e. the nodes it outputs to. Store the blocks for retrieval.
+    for edge in graph.edges(ix) {
+        let edge = edge.weight();
+        for conn in &edge.connections {
+            if let PortIndex::Port(idx) = conn.output_idx {
+                output_counts[idx as usize] -= 1;
+                // if there are no consumers left after this, take the data
+                let block = if output_counts[idx as usize] == 0 {
+                    out[conn.output_idx].take()
+                } else {
+                    out[conn.output_idx].clone()
+                };
+                *conn.cache.borrow_mut() = Some(block);
+            } else {
+                unreachable!()
             }
+        }
+    }
+}
 
-            // Count how many output connections fan out from each port
-            // This is so that we don't have to needlessly clone audio buffers
-            //
-            // If this is inefficient, we can instead maintain this data
-            // cached on the node
-            output_counts.clear();
-            output_counts.resize(curr.output_count() as usize, 0);
-            for edge in self.graph.edges(ix) {
-                let edge = edge.weight();
-                for conn in &edge.connections {
-                    if let PortIndex::Port(idx) = conn.output_idx {
-                        output_counts[idx as usize] += 1;
-                    } else {
-                        unreachable!()
+impl Schedule {
+    fn new(graph: &StableGraph<Node, Edge>, dest_id: NodeId) -> Self {
+        // DFS post order: Children are processed before their parent,
+        // which is exactly what we need since the parent depends on the
+        // children's output
+        //
+        // This will only visit each node once
+        let reversed = Reversed(graph);
+        let mut visit = DfsPostOrder::new(reversed, dest_id.0);
+
+        // The level of every visited node, by node index
+        let mut levels = Vec::new();
+        levels.resize(graph.node_bound(), 0);
+
+        let mut nodes = Vec::new();
+        while let Some(ix) = visit.next(reversed) {
+            let mut node = graph[ix].node.borrow_mut();
+
+            // All the dependencies have been visited already
+            let level = graph
+                .edges_directed(ix, Direction::Incoming)
+                .map(|edge| levels[edge.source().index()] + 1)
+                .max()
+                .unwrap_or(0);
+            levels[ix.index()] = level;
+
+            let mut input_counts = SmallVec::new();
+            input_counts.resize(node.input_count() as usize, 0);
+            let mut params: SmallVec<[ParamType; 2]> = SmallVec::new();
+            for edge in graph.edges_directed(ix, Direction::Incoming) {
+                for conn in &edge.weight().connections {
+                    match conn.input_idx {
+                        PortIndex::Port(idx) => input_counts[idx as usize] += 1,
+                        PortIndex::Param(param) => params.push(param),
                     }
                 }
             }
 
-            // all the edges from this node go to nodes which depend on it,
-            // i.e. the nodes it outputs to. Store the blocks for retrieval.
-            for edge in self.graph.edges(ix) {
-                let edge = edge.weight();
-                for conn in &edge.connections {
+            // The node may only start processing the inputs of its
+            // params later on, make room for them up front
+            for &param in &params {
+                let inputs = params.iter().filter(|p| **p == param).count();
+                if let Ok(param) = node.get_param(param) {
+                    param.reserve_inputs(inputs);
+                }
+            }
+
+            let mut output_counts = SmallVec::new();
+            output_counts.resize(node.output_count() as usize, 0);
+            for edge in graph.edges(ix) {
+                for conn in &edge.weight().connections {
                     if let PortIndex::Port(idx) = conn.output_idx {
-                        output_counts[idx as usize] -= 1;
-                        // if there are no consumers left after this, take the data
-                        let block = if output_counts[idx as usize] == 0 {
-                            out[conn.output_idx].take()
-                        } else {
-                            out[conn.output_idx].clone()
-                        };
-                        *conn.cache.borrow_mut() = Some(block);
+                        output_counts[idx as usize] += 1;
                     } else {
                         unreachable!()
                     }
                 }
             }
-        }
 
-        // The destination node stores its output on itself, extract it.
-        self.graph[self.dest_id.0]
-            .node
-            .borrow_mut()
-            .destination_data()
-            .expect("Destination node should have data cached")
-    }
+            nodes.push(ScheduledNode {
+                id: ix,
+                input_counts,
+                output_counts,
+            });
+        }
 
-    /// Obtain a mutable reference to a node
-    pub(crate) fn node_mut(&self, ix: NodeId) -> RefMut<Box<AudioNodeEngine>> {
-        self.graph[ix.0].node.borrow_mut()
+        // This is a stable sort, so nodes keep their post order within a level
+        nodes.sort_by_key(|node| levels[node.id.index()]);
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (i, node) in nodes.iter().enumerate() {
+            let level = levels[node.id.index()];
+            if ranges.len() <= level {
+                ranges.push(i..i);
+            }
+            ranges[level].end = i + 1;
+        }
+        Schedule {
+            nodes,
+            levels: ranges,
+        }
     }
 }
 
@@ -471,6 +1259,9 @@ impl Node {
     pub fn new(node: Box<AudioNodeEngine>) -> Self {
         Node {
             node: RefCell::new(node),
+            tail_end: Cell::new(Tick(0)),
+            profile: Cell::new(NodeProfile::default()),
+            mixing: None,
         }
     }
 }
@@ -484,11 +1275,26 @@ impl Edge {
 }
 
 impl Connection {
+    /// Fade the block going through this connection in or out,
+    /// if the connection was just made or is being removed
+    fn apply_fade(&self, block: &mut Block, ramps: &FadeRamps) {
+        let gains = match self.fade.get() {
+            Fade::None | Fade::Out => return,
+            Fade::In => {
+                self.fade.set(Fade::None);
+                &ramps.fade_in
+            }
+            Fade::FadingOut => &ramps.fade_out,
+        };
+        block.scale_by(gains);
+    }
+
     pub fn new(input_idx: PortIndex<InputPort>, output_idx: PortIndex<OutputPort>) -> Self {
         Connection {
             input_idx,
             output_idx,
             cache: RefCell::new(None),
+            fade: Cell::new(Fade::None),
         }
     }
 }
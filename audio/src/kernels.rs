@@ -0,0 +1,312 @@
+//! Vectorized kernels for the inner loops of block processing.
+//!
+//! Every kernel has a scalar implementation, and on x86 an SSE and an AVX
+//! one. The fastest implementation supported by the CPU is picked at
+//! runtime. The vectorized versions perform the same operations in the
+//! same order as the scalar ones, so all of them produce identical output.
+
+/// Pick the best implementation of a kernel for the running CPU
+macro_rules! dispatch(
+    ($kernel:ident($($arg:expr),*)) => ({
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx") {
+                return unsafe { x86::avx::$kernel($($arg),*) };
+            }
+            if is_x86_feature_detected!("sse") {
+                return unsafe { x86::sse::$kernel($($arg),*) };
+            }
+        }
+        scalar::$kernel($($arg),*)
+    });
+);
+
+/// `dst[i] += src[i]`
+pub fn add(dst: &mut [f32], src: &[f32]) {
+    debug_assert!(dst.len() == src.len());
+    dispatch!(add(dst, src))
+}
+
+/// `dst[i] *= gain`
+pub fn scale(dst: &mut [f32], gain: f32) {
+    dispatch!(scale(dst, gain))
+}
+
+/// `dst[i] *= gains[i]`
+pub fn mul(dst: &mut [f32], gains: &[f32]) {
+    debug_assert!(dst.len() == gains.len());
+    dispatch!(mul(dst, gains))
+}
+
+/// `dst[i] += src[i] * gain`
+pub fn add_scaled(dst: &mut [f32], src: &[f32], gain: f32) {
+    debug_assert!(dst.len() == src.len());
+    dispatch!(add_scaled(dst, src, gain))
+}
+
+/// Interleave `channels` planar channels of `frames` frames each
+/// from `src` into `dst`
+pub fn interleave(dst: &mut [f32], src: &[f32], channels: usize, frames: usize) {
+    // the vectorized implementation relies on this for memory safety
+    assert!(dst.len() == channels * frames && src.len() == channels * frames);
+    // Only stereo, by far the most common output, has a vectorized
+    // implementation
+    if channels == 2 {
+        dispatch!(interleave_stereo(dst, src, frames))
+    } else {
+        scalar::interleave(dst, src, channels, frames)
+    }
+}
+
+pub mod scalar {
+    //! Portable implementations, used when no vector unit is available
+    //! and for the remainders the vectorized kernels leave over.
+
+    pub fn add(dst: &mut [f32], src: &[f32]) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d += *s;
+        }
+    }
+
+    pub fn scale(dst: &mut [f32], gain: f32) {
+        for d in dst.iter_mut() {
+            *d *= gain;
+        }
+    }
+
+    pub fn mul(dst: &mut [f32], gains: &[f32]) {
+        for (d, g) in dst.iter_mut().zip(gains) {
+            *d *= *g;
+        }
+    }
+
+    pub fn add_scaled(dst: &mut [f32], src: &[f32], gain: f32) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d += *s * gain;
+        }
+    }
+
+    pub fn interleave(dst: &mut [f32], src: &[f32], channels: usize, frames: usize) {
+        for frame in 0..frames {
+            for chan in 0..channels {
+                dst[frame * channels + chan] = src[chan * frames + frame];
+            }
+        }
+    }
+
+    pub fn interleave_stereo(dst: &mut [f32], src: &[f32], frames: usize) {
+        interleave(dst, src, 2, frames)
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    /// Generate the vectorized kernels for one instruction set
+    macro_rules! kernels(
+        (
+            $feature:tt,
+            $lanes:expr,
+            $load:ident,
+            $store:ident,
+            $set1:ident,
+            $add:ident,
+            $mul:ident
+        ) => (
+            #[cfg(target_arch = "x86")]
+            use std::arch::x86::*;
+            #[cfg(target_arch = "x86_64")]
+            use std::arch::x86_64::*;
+            use kernels::scalar;
+            use std::cmp;
+
+            const LANES: usize = $lanes;
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn add(dst: &mut [f32], src: &[f32]) {
+                let len = cmp::min(dst.len(), src.len());
+                let vectorized = len - len % LANES;
+                let (d, s) = (dst.as_mut_ptr(), src.as_ptr());
+                let mut i = 0;
+                while i < vectorized {
+                    $store(d.add(i), $add($load(d.add(i)), $load(s.add(i))));
+                    i += LANES;
+                }
+                scalar::add(&mut dst[vectorized..len], &src[vectorized..len]);
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn scale(dst: &mut [f32], gain: f32) {
+                let vectorized = dst.len() - dst.len() % LANES;
+                let (d, g) = (dst.as_mut_ptr(), $set1(gain));
+                let mut i = 0;
+                while i < vectorized {
+                    $store(d.add(i), $mul($load(d.add(i)), g));
+                    i += LANES;
+                }
+                scalar::scale(&mut dst[vectorized..], gain);
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn mul(dst: &mut [f32], gains: &[f32]) {
+                let len = cmp::min(dst.len(), gains.len());
+                let vectorized = len - len % LANES;
+                let (d, g) = (dst.as_mut_ptr(), gains.as_ptr());
+                let mut i = 0;
+                while i < vectorized {
+                    $store(d.add(i), $mul($load(d.add(i)), $load(g.add(i))));
+                    i += LANES;
+                }
+                scalar::mul(&mut dst[vectorized..len], &gains[vectorized..len]);
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn add_scaled(dst: &mut [f32], src: &[f32], gain: f32) {
+                let len = cmp::min(dst.len(), src.len());
+                let vectorized = len - len % LANES;
+                let (d, s, g) = (dst.as_mut_ptr(), src.as_ptr(), $set1(gain));
+                let mut i = 0;
+                while i < vectorized {
+                    $store(d.add(i), $add($load(d.add(i)), $mul($load(s.add(i)), g)));
+                    i += LANES;
+                }
+                scalar::add_scaled(&mut dst[vectorized..len], &src[vectorized..len], gain);
+            }
+        );
+    );
+
+    pub mod sse {
+        kernels!("sse", 4, _mm_loadu_ps, _mm_storeu_ps, _mm_set1_ps, _mm_add_ps, _mm_mul_ps);
+
+        #[target_feature(enable = "sse")]
+        pub unsafe fn interleave_stereo(dst: &mut [f32], src: &[f32], frames: usize) {
+            let vectorized = frames - frames % LANES;
+            let (d, l, r) = (dst.as_mut_ptr(), src.as_ptr(), src.as_ptr().add(frames));
+            let mut i = 0;
+            while i < vectorized {
+                let (left, right) = (_mm_loadu_ps(l.add(i)), _mm_loadu_ps(r.add(i)));
+                // [l0, r0, l1, r1] and [l2, r2, l3, r3]
+                _mm_storeu_ps(d.add(2 * i), _mm_unpacklo_ps(left, right));
+                _mm_storeu_ps(d.add(2 * i + LANES), _mm_unpackhi_ps(left, right));
+                i += LANES;
+            }
+            for frame in vectorized..frames {
+                dst[2 * frame] = src[frame];
+                dst[2 * frame + 1] = src[frames + frame];
+            }
+        }
+    }
+
+    pub mod avx {
+        kernels!(
+            "avx",
+            8,
+            _mm256_loadu_ps,
+            _mm256_storeu_ps,
+            _mm256_set1_ps,
+            _mm256_add_ps,
+            _mm256_mul_ps
+        );
+
+        #[target_feature(enable = "avx")]
+        pub unsafe fn interleave_stereo(dst: &mut [f32], src: &[f32], frames: usize) {
+            // Shuffles in AVX do not cross 128 bit lanes, which makes
+            // them awkward for interleaving, SSE does the job just as well
+            super::sse::interleave_stereo(dst, src, frames)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lengths around the vector widths, so that the tails are covered
+    const LENGTHS: [usize; 8] = [0, 1, 3, 5, 7, 9, 17, 131];
+
+    fn samples(len: usize, seed: u32) -> Vec<f32> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as f32 / 2_147_483_648. - 1.
+            })
+            .collect()
+    }
+
+    /// Check a kernel against the scalar one, for every
+    /// implementation the running CPU supports
+    fn check<F, G>(scalar: F, kernel: G)
+    where
+        F: Fn(&mut [f32], &[f32]),
+        G: Fn(&mut [f32], &[f32]),
+    {
+        for &len in &LENGTHS {
+            let src = samples(len, 0x1234_5678);
+            let mut expected = samples(len, 0x9E37_79B9);
+            let mut actual = expected.clone();
+            scalar(&mut expected, &src);
+            kernel(&mut actual, &src);
+            assert_eq!(actual, expected, "length {}", len);
+        }
+    }
+
+    macro_rules! check_kernel(
+        ($name:ident, |$dst:ident, $src:ident| $kernel:ident($($arg:expr),*)) => (
+            #[test]
+            fn $name() {
+                check(|$dst, $src| scalar::$kernel($($arg),*), |$dst, $src| $kernel($($arg),*));
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                {
+                    if is_x86_feature_detected!("sse") {
+                        check(
+                            |$dst, $src| scalar::$kernel($($arg),*),
+                            |$dst, $src| unsafe { x86::sse::$kernel($($arg),*) },
+                        );
+                    }
+                    if is_x86_feature_detected!("avx") {
+                        check(
+                            |$dst, $src| scalar::$kernel($($arg),*),
+                            |$dst, $src| unsafe { x86::avx::$kernel($($arg),*) },
+                        );
+                    }
+                }
+            }
+        );
+    );
+
+    check_kernel!(add_matches_scalar, |dst, src| add(dst, src));
+    check_kernel!(scale_matches_scalar, |dst, _src| scale(dst, 0.7));
+    check_kernel!(mul_matches_scalar, |dst, src| mul(dst, src));
+    check_kernel!(add_scaled_matches_scalar, |dst, src| add_scaled(dst, src, -0.3));
+
+    #[test]
+    fn interleave_matches_scalar() {
+        for &frames in &LENGTHS {
+            for channels in 1..4 {
+                let src = samples(channels * frames, 0x1234_5678);
+                let mut expected = vec![0.; src.len()];
+                let mut actual = vec![0.; src.len()];
+                scalar::interleave(&mut expected, &src, channels, frames);
+                interleave(&mut actual, &src, channels, frames);
+                assert_eq!(actual, expected, "{} channels of {}", channels, frames);
+            }
+            let src = samples(2 * frames, 0x1234_5678);
+            let mut expected = vec![0.; src.len()];
+            scalar::interleave(&mut expected, &src, 2, frames);
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            {
+                let mut actual = vec![0.; src.len()];
+                if is_x86_feature_detected!("sse") {
+                    unsafe { x86::sse::interleave_stereo(&mut actual, &src, frames) };
+                    assert_eq!(actual, expected, "sse, {} frames", frames);
+                }
+                if is_x86_feature_detected!("avx") {
+                    unsafe { x86::avx::interleave_stereo(&mut actual, &src, frames) };
+                    assert_eq!(actual, expected, "avx, {} frames", frames);
+                }
+            }
+        }
+    }
+}
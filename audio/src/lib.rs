@@ -7,24 +7,42 @@ extern crate servo_media_derive;
 extern crate byte_slice_cast;
 extern crate num_traits;
 extern crate petgraph;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate smallvec;
 #[macro_use]
 pub mod macros;
 
+#[cfg(feature = "alloc_check")]
+pub mod alloc_check;
+
 pub mod block;
 pub mod buffer_source_node;
+pub mod channel_layout;
 pub mod channel_node;
 pub mod context;
+pub mod control_queue;
 pub mod decoder;
 pub mod destination_node;
+pub mod error;
 pub mod gain_node;
 pub mod graph;
+pub mod kernels;
 pub mod node;
+pub mod offline_renderer;
 pub mod offline_sink;
 pub mod oscillator_node;
 pub mod param;
+pub mod pool;
+pub mod preset;
+pub mod pull_renderer;
+pub mod render_capacity;
 pub mod render_thread;
+pub mod sample_format;
 pub mod sink;
+pub mod snapshot;
+pub mod worker_pool;
 
 pub trait AudioBackend {
     type Decoder: decoder::AudioDecoder;
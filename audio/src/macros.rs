@@ -16,12 +16,11 @@ macro_rules! make_message_handler(
 
 #[macro_export]
 macro_rules! make_state_change(
-    ($fn_name:ident, $state:ident, $render_msg:ident) => (
+    ($fn_name:ident, $render_msg:ident) => (
         pub fn $fn_name(&self) -> StateChangeResult {
-            self.state.set(ProcessingState::$state);
             let (tx, rx) = mpsc::channel();
             let _ = self.sender.send(AudioRenderThreadMsg::$render_msg(tx));
-            rx.recv().unwrap()
+            rx.recv().unwrap_or(Err(()))
         }
     );
 );
@@ -33,8 +32,17 @@ macro_rules! make_render_thread_state_change(
             if self.state == ProcessingState::$state {
                 return Ok(());
             }
-            self.state = ProcessingState::$state;
-            self.sink.$sink_method()
+            // a closed context stays closed
+            if self.state == ProcessingState::Closed {
+                return Err(());
+            }
+            let result = self.sink.$sink_method();
+            self.set_state(if result.is_ok() {
+                ProcessingState::$state
+            } else {
+                ProcessingState::Interrupted
+            });
+            result
         }
     );
 );
@@ -1,6 +1,7 @@
 use block::{Chunk, Tick};
 use buffer_source_node::{AudioBufferSourceNodeMessage, AudioBufferSourceNodeOptions};
 use channel_node::ChannelNodeOptions;
+use error::{AudioError, AudioResult};
 use gain_node::GainNodeOptions;
 use oscillator_node::OscillatorNodeOptions;
 use param::{Param, ParamRate, ParamType, UserAutomationEvent};
@@ -8,7 +9,7 @@ use std::boxed::FnBox;
 use std::sync::mpsc::Sender;
 
 /// Information required to construct an audio node
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AudioNodeInit {
     AnalyserNode,
     BiquadFilterNode,
@@ -55,14 +56,14 @@ pub enum AudioNodeType {
     WaveShaperNode,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum ChannelCountMode {
     Max,
     ClampedMax,
     Explicit,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum ChannelInterpretation {
     Discrete,
     Speakers,
@@ -73,6 +74,9 @@ pub struct BlockInfo {
     pub sample_rate: f32,
     pub frame: Tick,
     pub time: f64,
+    /// The number of frames of the block, i.e. the render
+    /// quantum size of the context
+    pub frames: usize,
 }
 
 impl BlockInfo {
@@ -83,6 +87,12 @@ impl BlockInfo {
     }
 }
 
+/// The number of channels every node must support, and the most we do
+///
+/// https://webaudio.github.io/web-audio-api/#dom-baseaudiocontext-createbuffer
+pub const MAX_CHANNEL_COUNT: u8 = 32;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct ChannelInfo {
     pub count: u8,
     pub mode: ChannelCountMode,
@@ -109,22 +119,27 @@ pub(crate) trait AudioNodeCommon {
 pub(crate) trait AudioNodeEngine: Send + AudioNodeCommon {
     fn node_type(&self) -> AudioNodeType;
 
+    /// The options for creating a node set up like this one,
+    /// e.g. for saving it into a preset
+    fn init_options(&self) -> AudioNodeInit;
+
     fn process(&mut self, inputs: Chunk, info: &BlockInfo) -> Chunk;
 
-    fn message(&mut self, msg: AudioNodeMessage, sample_rate: f32) {
+    fn message(&mut self, msg: AudioNodeMessage, sample_rate: f32) -> AudioResult<()> {
         match msg {
             AudioNodeMessage::GetParamValue(id, tx) => {
-                let _ = tx.send(self.get_param(id).value());
+                let _ = tx.send(self.get_param(id).map(|p| p.value()));
             }
-            AudioNodeMessage::SetChannelCount(c) => self.set_channel_count(c),
-            AudioNodeMessage::SetChannelMode(c) => self.set_channel_count_mode(c),
-            AudioNodeMessage::SetChannelInterpretation(c) => self.set_channel_interpretation(c),
+            AudioNodeMessage::SetChannelCount(c) => self.set_channel_count(c)?,
+            AudioNodeMessage::SetChannelMode(c) => self.set_channel_count_mode(c)?,
+            AudioNodeMessage::SetChannelInterpretation(c) => self.set_channel_interpretation(c)?,
             AudioNodeMessage::SetParam(id, event) => {
-                self.get_param(id).insert_event(event.to_event(sample_rate))
+                self.get_param(id)?.insert_event(event.to_event(sample_rate))
             }
-            AudioNodeMessage::SetParamRate(id, rate) => self.get_param(id).set_rate(rate),
+            AudioNodeMessage::SetParamRate(id, rate) => self.get_param(id)?.set_rate(rate),
             _ => self.message_specific(msg, sample_rate),
         }
+        Ok(())
     }
 
     /// Messages specific to this node
@@ -150,14 +165,20 @@ pub(crate) trait AudioNodeEngine: Send + AudioNodeCommon {
         self.channel_info().interpretation
     }
 
-    fn set_channel_interpretation(&mut self, i: ChannelInterpretation) {
-        self.channel_info_mut().interpretation = i
+    fn set_channel_interpretation(&mut self, i: ChannelInterpretation) -> AudioResult<()> {
+        self.channel_info_mut().interpretation = i;
+        Ok(())
     }
-    fn set_channel_count(&mut self, c: u8) {
+    fn set_channel_count(&mut self, c: u8) -> AudioResult<()> {
+        if c == 0 || c > MAX_CHANNEL_COUNT {
+            return Err(AudioError::NotSupported);
+        }
         self.channel_info_mut().count = c;
+        Ok(())
     }
-    fn set_channel_count_mode(&mut self, m: ChannelCountMode) {
+    fn set_channel_count_mode(&mut self, m: ChannelCountMode) -> AudioResult<()> {
         self.channel_info_mut().mode = m;
+        Ok(())
     }
 
     /// If we're the destination node, extract the contained data
@@ -165,15 +186,37 @@ pub(crate) trait AudioNodeEngine: Send + AudioNodeCommon {
         None
     }
 
-    fn get_param(&mut self, _: ParamType) -> &mut Param {
-        panic!("No params on node {:?}", self.node_type())
+    /// How long this node may keep producing non-silent output after
+    /// its inputs have gone silent, e.g. the length of a delay line
+    ///
+    /// https://webaudio.github.io/web-audio-api/#tail-time
+    fn tail_time(&self) -> Tick {
+        Tick(0)
+    }
+
+    /// Whether this node needs to be processed for the given block
+    /// even though its inputs are silent and its tail time is over,
+    /// e.g. a source node that is playing
+    ///
+    /// Nodes that are not actively processing are skipped, silence
+    /// being passed on for their outputs instead.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#actively-processing
+    fn is_active(&self, _info: &BlockInfo) -> bool {
+        false
+    }
+
+    /// Obtain one of this node's params, failing with `InvalidAccess`
+    /// if the node has no such param
+    fn get_param(&mut self, _: ParamType) -> AudioResult<&mut Param> {
+        Err(AudioError::InvalidAccess)
     }
 }
 
 pub enum AudioNodeMessage {
     AudioBufferSourceNode(AudioBufferSourceNodeMessage),
     AudioScheduledSourceNode(AudioScheduledSourceNodeMessage),
-    GetParamValue(ParamType, Sender<f32>),
+    GetParamValue(ParamType, Sender<AudioResult<f32>>),
     SetChannelCount(u8),
     SetChannelMode(ChannelCountMode),
     SetChannelInterpretation(ChannelInterpretation),
@@ -187,6 +230,16 @@ impl OnEndedCallback {
     pub fn new<F: FnOnce() + Send + 'static>(callback: F) -> Self {
         OnEndedCallback(Box::new(callback))
     }
+
+    /// Run the callback. This happens while rendering, but what the
+    /// embedder does in it is out of our hands, so it is exempt from
+    /// allocation checks.
+    pub fn call(self) {
+        #[cfg(feature = "alloc_check")]
+        ::alloc_check::allow(|| (self.0)());
+        #[cfg(not(feature = "alloc_check"))]
+        (self.0)();
+    }
 }
 
 /// Type of message directed to AudioScheduledSourceNodes.
@@ -0,0 +1,152 @@
+//! Rendering a graph on the calling thread, as fast as it can go.
+//!
+//! An offline `AudioContext` still renders on its own thread and is driven
+//! through messages, like a realtime one. For batch jobs and tests, an
+//! `OfflineRenderer` owns the graph and renders the frames asked for right
+//! away, with changes to the graph applied between render quanta in the
+//! order they were made.
+
+use block::{Block, Tick, FRAMES_PER_BLOCK_USIZE};
+use buffer_source_node::AudioBuffer;
+use context::{check_channels, check_render_size};
+use error::AudioResult;
+use graph::{AudioGraph, InputPort, NodeId, OutputPort, PortId};
+use node::{AudioNodeInit, AudioNodeMessage, BlockInfo, ChannelInterpretation};
+use render_thread::make_node;
+
+pub struct OfflineRendererOptions {
+    /// The number of channels of the rendered audio.
+    pub channels: u8,
+    /// The sample rate of the rendered audio.
+    pub sample_rate: f32,
+    /// Number of frames in a render quantum.
+    pub render_size: usize,
+    /// Number of threads rendering the graph, counting the calling one.
+    pub render_threads: usize,
+}
+
+impl Default for OfflineRendererOptions {
+    fn default() -> Self {
+        Self {
+            channels: 1,
+            sample_rate: 44100.,
+            render_size: FRAMES_PER_BLOCK_USIZE,
+            render_threads: 1,
+        }
+    }
+}
+
+pub struct OfflineRenderer {
+    graph: AudioGraph,
+    channels: u8,
+    sample_rate: f32,
+    /// Number of frames in a render quantum
+    render_size: usize,
+    /// The first frame of the next render quantum
+    current_frame: Tick,
+    /// The last render quantum, if it was not handed out entirely,
+    /// and the first of its frames left to hand out
+    pending: Option<(Block, usize)>,
+}
+
+impl OfflineRenderer {
+    /// Make a renderer with an empty graph, failing with `NotSupported`
+    /// for a render size or a number of channels out of range
+    pub fn new(options: OfflineRendererOptions) -> AudioResult<Self> {
+        check_render_size(options.render_size)?;
+        check_channels(options.channels)?;
+        let mut graph = AudioGraph::new();
+        graph.set_render_threads(options.render_threads);
+        let dest = graph.dest_id();
+        graph.node_mut(dest)?.set_channel_count(options.channels)?;
+        Ok(Self {
+            graph,
+            channels: options.channels,
+            sample_rate: options.sample_rate,
+            render_size: options.render_size,
+            current_frame: Tick(0),
+            pending: None,
+        })
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    /// The time of the next render quantum, which is ahead of the frames
+    /// handed out so far if the last render did not end on a quantum
+    pub fn current_time(&self) -> f64 {
+        self.current_frame / self.sample_rate as f64
+    }
+
+    pub fn dest_node(&self) -> NodeId {
+        self.graph.dest_id()
+    }
+
+    /// The graph, for the changes that have no shorthand here
+    pub fn graph_mut(&mut self) -> &mut AudioGraph {
+        &mut self.graph
+    }
+
+    pub fn create_node(&mut self, node_type: AudioNodeInit) -> AudioResult<NodeId> {
+        let node = make_node(node_type)?;
+        Ok(self.graph.add_node(node))
+    }
+
+    pub fn connect_ports(
+        &mut self,
+        output: PortId<OutputPort>,
+        input: PortId<InputPort>,
+    ) -> AudioResult<()> {
+        self.graph.add_edge(output, input)
+    }
+
+    pub fn message_node(&mut self, id: NodeId, msg: AudioNodeMessage) -> AudioResult<()> {
+        self.graph.node_mut(id)?.message(msg, self.sample_rate)
+    }
+
+    /// Render the next `frames` frames. Changes made since the last call
+    /// apply from the next render quantum, i.e. after the frames left over
+    /// from the last quantum rendered then.
+    pub fn render(&mut self, frames: usize) -> AudioBuffer {
+        let channels = self.channels as usize;
+        let mut buffers = vec![Vec::with_capacity(frames); channels];
+        while buffers[0].len() < frames {
+            let (block, offset) = match self.pending.take() {
+                Some(pending) => pending,
+                None => (self.render_quantum(), 0),
+            };
+            let end = self.render_size.min(offset + frames - buffers[0].len());
+            for (chan, buffer) in buffers.iter_mut().enumerate() {
+                buffer.extend_from_slice(&block.data_chan(chan as u8)[offset..end]);
+            }
+            if end < self.render_size {
+                self.pending = Some((block, end));
+            }
+        }
+        AudioBuffer::from_buffers(buffers)
+    }
+
+    fn render_quantum(&mut self) -> Block {
+        let info = BlockInfo {
+            sample_rate: self.sample_rate,
+            frame: self.current_frame,
+            time: self.current_time(),
+            frames: self.render_size,
+        };
+        let mut chunk = self.graph.process(&info);
+        self.current_frame += Tick(self.render_size as u64);
+        let mut block = if chunk.len() == 0 {
+            Block::new(self.render_size)
+        } else {
+            chunk.blocks[0].take()
+        };
+        block.mix(self.channels, ChannelInterpretation::Speakers);
+        block.explicit_silence();
+        block
+    }
+}
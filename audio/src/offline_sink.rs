@@ -1,8 +1,12 @@
-use block::{Chunk, FRAMES_PER_BLOCK_USIZE};
+use block::Chunk;
+use buffer_source_node::AudioBuffer;
+use error::{AudioError, AudioResult};
 use render_thread::AudioRenderThreadMsg;
 use sink::AudioSink;
 use std::cell::{Cell, RefCell};
-use std::sync::mpsc::Sender;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 
 pub struct ProcessedAudio(Box<[f32]>);
 
@@ -12,26 +16,198 @@ impl AsRef<[f32]> for ProcessedAudio {
     }
 }
 
+/// How far an offline context is into rendering
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderProgress {
+    /// Number of frames rendered so far
+    pub rendered: usize,
+    /// Number of frames to render in total
+    pub length: usize,
+}
+
+impl RenderProgress {
+    /// The fraction of the frames rendered so far
+    pub fn fraction(&self) -> f64 {
+        if self.length == 0 {
+            return 1.;
+        }
+        self.rendered as f64 / self.length as f64
+    }
+}
+
+/// A block of audio rendered by an offline context
+pub struct RenderedBlock<'a> {
+    /// The samples of each channel, one channel after the other
+    pub data: &'a [f32],
+    pub channels: usize,
+    /// Number of frames, which is less than the render quantum
+    /// for the last block if the length is not a multiple of it
+    pub frames: usize,
+    pub progress: RenderProgress,
+}
+
+impl<'a> RenderedBlock<'a> {
+    pub fn channel(&self, chan: usize) -> &[f32] {
+        &self.data[chan * self.frames..(chan + 1) * self.frames]
+    }
+}
+
+/// Where an offline context puts the audio it renders
+pub enum OfflineOutput {
+    /// In a buffer holding the whole rendering, handed over at the end
+    Buffer,
+    /// To a callback, one block at a time as they are rendered, so that
+    /// long renderings do not need to be held in memory. The buffer
+    /// handed over at the end is then empty.
+    Blocks(Box<FnMut(&RenderedBlock) + Send + 'static>),
+}
+
+impl Default for OfflineOutput {
+    fn default() -> Self {
+        OfflineOutput::Buffer
+    }
+}
+
+impl OfflineOutput {
+    /// Write the rendered audio to `writer` as interleaved little endian
+    /// f32 samples, calling `progress` after each block. Writing stops at
+    /// the first error, which is passed to `progress`.
+    pub fn writer<W, F>(writer: W, progress: F) -> Self
+    where
+        W: Write + Send + 'static,
+        F: FnMut(io::Result<RenderProgress>) + Send + 'static,
+    {
+        let mut writer = Some(writer);
+        let mut progress = progress;
+        let mut bytes = Vec::new();
+        OfflineOutput::Blocks(Box::new(move |block: &RenderedBlock| {
+            let result = match writer {
+                Some(ref mut writer) => {
+                    bytes.clear();
+                    for frame in 0..block.frames {
+                        for chan in 0..block.channels {
+                            let bits = block.data[chan * block.frames + frame].to_bits();
+                            bytes.extend_from_slice(&[
+                                bits as u8,
+                                (bits >> 8) as u8,
+                                (bits >> 16) as u8,
+                                (bits >> 24) as u8,
+                            ]);
+                        }
+                    }
+                    let mut result = writer.write_all(&bytes);
+                    if block.progress.rendered == block.progress.length {
+                        result = result.and_then(|_| writer.flush());
+                    }
+                    result
+                }
+                // an error was already reported
+                None => return,
+            };
+            match result {
+                Ok(()) => progress(Ok(block.progress)),
+                Err(error) => {
+                    writer = None;
+                    progress(Err(error));
+                }
+            }
+        }))
+    }
+}
+
+/// The audio an offline context is rendering, see
+/// `AudioContext::start_rendering`
+pub struct OfflineRendering {
+    receiver: Receiver<AudioBuffer>,
+    sample_rate: f32,
+}
+
+impl OfflineRendering {
+    pub(crate) fn new(receiver: Receiver<AudioBuffer>, sample_rate: f32) -> Self {
+        OfflineRendering {
+            receiver,
+            sample_rate,
+        }
+    }
+
+    /// The sample rate of the rendered audio
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Wait for rendering to finish. Fails with `InvalidState` if the
+    /// context is closed before that.
+    pub fn wait(self) -> AudioResult<AudioBuffer> {
+        self.receiver.recv().map_err(|_| AudioError::InvalidState)
+    }
+
+    /// The rendered audio if rendering is done, without waiting.
+    /// The audio can only be obtained once.
+    pub fn try_wait(&self) -> Option<AudioResult<AudioBuffer>> {
+        match self.receiver.try_recv() {
+            Ok(buffer) => Some(Ok(buffer)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(AudioError::InvalidState)),
+        }
+    }
+}
+
 pub struct OfflineAudioSink {
     buffer: RefCell<Option<Vec<f32>>>,
     channel_count: usize,
     has_enough_data: Cell<bool>,
     length: usize,
-    rendered_blocks: Cell<usize>,
+    /// Number of frames rendered so far
+    rendered_frames: Cell<usize>,
     eos_callback: RefCell<Option<Box<Fn(Box<AsRef<[f32]>>) + Send + Sync + 'static>>>,
+    /// Where to send the rendered audio, if rendering was
+    /// started with `AudioContext::start_rendering`
+    rendering: RefCell<Option<Sender<AudioBuffer>>>,
+    /// Where blocks go as they are rendered, instead of `buffer`
+    blocks: RefCell<Option<Box<FnMut(&RenderedBlock) + Send + 'static>>>,
+    /// Room for the channels of a block, for handing it to `blocks`
+    scratch: RefCell<Vec<f32>>,
 }
 
 impl OfflineAudioSink {
-    pub fn new(channel_count: usize, length: usize) -> Self {
+    pub fn new(channel_count: usize, length: usize, output: OfflineOutput) -> Self {
+        let blocks = match output {
+            OfflineOutput::Buffer => None,
+            OfflineOutput::Blocks(callback) => Some(callback),
+        };
         Self {
             buffer: RefCell::new(None),
             channel_count,
             has_enough_data: Cell::new(false),
             length,
-            rendered_blocks: Cell::new(0),
+            rendered_frames: Cell::new(0),
             eos_callback: RefCell::new(None),
+            rendering: RefCell::new(None),
+            blocks: RefCell::new(blocks),
+            scratch: RefCell::new(Vec::new()),
         }
     }
+
+    /// Number of frames to render
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Whether all the frames were rendered
+    pub fn finished(&self) -> bool {
+        self.rendered_frames.get() >= self.length
+    }
+
+    /// Send the rendered audio to `sender` once done. Fails with
+    /// `InvalidState` if rendering was already started this way.
+    pub fn set_rendering(&self, sender: Sender<AudioBuffer>) -> AudioResult<()> {
+        let mut rendering = self.rendering.borrow_mut();
+        if rendering.is_some() || self.rendered_frames.get() > 0 {
+            return Err(AudioError::InvalidState);
+        }
+        *rendering = Some(sender);
+        Ok(())
+    }
 }
 
 impl AudioSink for OfflineAudioSink {
@@ -50,27 +226,39 @@ impl AudioSink for OfflineAudioSink {
     }
 
     fn has_enough_data(&self) -> bool {
-        self.has_enough_data.get()
-            || (self.rendered_blocks.get() * FRAMES_PER_BLOCK_USIZE >= self.length)
+        self.has_enough_data.get() || self.finished()
     }
 
     fn push_data(&self, mut chunk: Chunk) -> Result<(), ()> {
-        let offset = self.rendered_blocks.get() * FRAMES_PER_BLOCK_USIZE;
-        let (last, copy_len) = if self.length - offset <= FRAMES_PER_BLOCK_USIZE {
+        let frames = chunk.blocks[0].frames();
+        let offset = self.rendered_frames.get();
+        let (last, copy_len) = if self.length - offset <= frames {
             (true, self.length - offset)
         } else {
-            (false, FRAMES_PER_BLOCK_USIZE)
+            (false, frames)
         };
-        let mut buffer = self.buffer.borrow_mut();
-        if buffer.is_none() {
-            *buffer = Some(vec![0.; self.channel_count * self.length]);
-        }
-        if chunk.len() == 0 {
-            chunk.blocks.push(Default::default());
-        }
         if chunk.blocks[0].is_empty() {
             chunk.blocks[0].explicit_silence();
         }
+        let mut buffer = self.buffer.borrow_mut();
+        if let Some(ref mut callback) = *self.blocks.borrow_mut() {
+            let mut scratch = self.scratch.borrow_mut();
+            scratch.clear();
+            for chan in 0..self.channel_count {
+                scratch.extend_from_slice(&chunk.blocks[0].data_chan(chan as u8)[0..copy_len]);
+            }
+            callback(&RenderedBlock {
+                data: &scratch,
+                channels: self.channel_count,
+                frames: copy_len,
+                progress: RenderProgress {
+                    rendered: offset + copy_len,
+                    length: self.length,
+                },
+            });
+        } else if buffer.is_none() {
+            *buffer = Some(vec![0.; self.channel_count * self.length]);
+        }
         if let Some(ref mut buffer) = *buffer {
             for channel_number in 0..self.channel_count {
                 let channel_offset = offset + (channel_number * self.length);
@@ -79,13 +267,34 @@ impl AudioSink for OfflineAudioSink {
                 channel_data.copy_from_slice(&chunk.blocks[0].data_chan(channel_number as u8)[0..copy_len]);
             }
         };
-        self.rendered_blocks.update(|blocks| blocks + 1);
+        self.rendered_frames.set(offset + copy_len);
 
         if last {
+            // when streaming blocks there is no buffer, and the
+            // audio handed over is empty
+            let mut data = buffer.take().unwrap_or_default();
+            let length = data.len() / self.channel_count;
+            let rendering = self.rendering.borrow_mut().take();
             if let Some(callback) = self.eos_callback.borrow_mut().take() {
-                let processed_audio =
-                    ProcessedAudio(buffer.take().unwrap().into_boxed_slice());
-                callback(Box::new(processed_audio));
+                // only copied if the audio is also to be sent below
+                let data = if rendering.is_some() {
+                    data.clone()
+                } else {
+                    mem::replace(&mut data, Vec::new())
+                };
+                callback(Box::new(ProcessedAudio(data.into_boxed_slice())));
+            }
+            if let Some(sender) = rendering {
+                // Split the channels off the back, giving their room back
+                // as we go, so that the audio is never held twice over
+                let mut channels = Vec::with_capacity(self.channel_count);
+                for chan in (1..self.channel_count).rev() {
+                    channels.push(data.split_off(chan * length));
+                    data.shrink_to_fit();
+                }
+                channels.push(data);
+                channels.reverse();
+                let _ = sender.send(AudioBuffer::from_buffers(channels));
             }
         }
 
@@ -1,15 +1,16 @@
-use block::{Chunk, Tick};
+use block::{Block, Chunk, Tick};
+use error::{AudioError, AudioResult};
 use node::{AudioNodeEngine, AudioScheduledSourceNodeMessage, BlockInfo, OnEndedCallback};
-use node::{AudioNodeType, ChannelInfo};
+use node::{AudioNodeInit, AudioNodeType, ChannelInfo};
 use num_traits::cast::NumCast;
 use param::{Param, ParamType};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct PeriodicWaveOptions {
     // XXX https://webaudio.github.io/web-audio-api/#dictdef-periodicwaveoptions
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum OscillatorType {
     Sine,
     Square,
@@ -18,7 +19,7 @@ pub enum OscillatorType {
     Custom,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct OscillatorNodeOptions {
     pub oscillator_type: OscillatorType,
     pub freq: f32,
@@ -40,6 +41,8 @@ impl Default for OscillatorNodeOptions {
 #[derive(AudioScheduledSourceNode, AudioNodeCommon)]
 pub(crate) struct OscillatorNode {
     channel_info: ChannelInfo,
+    oscillator_type: OscillatorType,
+    periodic_wave_options: Option<PeriodicWaveOptions>,
     frequency: Param,
     detune: Param,
     phase: f64,
@@ -55,6 +58,8 @@ impl OscillatorNode {
     pub fn new(options: OscillatorNodeOptions) -> Self {
         Self {
             channel_info: Default::default(),
+            oscillator_type: options.oscillator_type,
+            periodic_wave_options: options.periodic_wave_options,
             frequency: Param::new(options.freq.into()),
             detune: Param::new(options.detune.into()),
             phase: 0.,
@@ -74,6 +79,15 @@ impl AudioNodeEngine for OscillatorNode {
         AudioNodeType::OscillatorNode
     }
 
+    fn init_options(&self) -> AudioNodeInit {
+        AudioNodeInit::OscillatorNode(OscillatorNodeOptions {
+            oscillator_type: self.oscillator_type,
+            freq: self.frequency.value(),
+            detune: self.detune.value(),
+            periodic_wave_options: self.periodic_wave_options,
+        })
+    }
+
     fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         // XXX Implement this properly and according to self.options
         // as defined in https://webaudio.github.io/web-audio-api/#oscillatornode
@@ -82,7 +96,7 @@ impl AudioNodeEngine for OscillatorNode {
 
         debug_assert!(inputs.len() == 0);
 
-        inputs.blocks.push(Default::default());
+        inputs.blocks.push(Block::new(info.frames));
 
         if self.should_play_at(info.frame) == (false, true) {
             self.maybe_trigger_onended_callback();
@@ -133,11 +147,15 @@ impl AudioNodeEngine for OscillatorNode {
         0
     }
 
-    fn get_param(&mut self, id: ParamType) -> &mut Param {
+    fn is_active(&self, info: &BlockInfo) -> bool {
+        self.should_process_at(info)
+    }
+
+    fn get_param(&mut self, id: ParamType) -> AudioResult<&mut Param> {
         match id {
-            ParamType::Frequency => &mut self.frequency,
-            ParamType::Detune => &mut self.detune,
-            _ => panic!("Unknown param {:?} for OscillatorNode", id),
+            ParamType::Frequency => Ok(&mut self.frequency),
+            ParamType::Detune => Ok(&mut self.detune),
+            _ => Err(AudioError::InvalidAccess),
         }
     }
 
@@ -1,8 +1,9 @@
 use block::Block;
 use block::Tick;
 use node::BlockInfo;
+use preset::ParamPreset;
 
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum ParamType {
     Frequency,
     Detune,
@@ -10,6 +11,16 @@ pub enum ParamType {
     PlaybackRate,
 }
 
+impl ParamType {
+    /// Every type of param, for enumerating the params of a node
+    pub const ALL: [ParamType; 4] = [
+        ParamType::Frequency,
+        ParamType::Detune,
+        ParamType::Gain,
+        ParamType::PlaybackRate,
+    ];
+}
+
 /// An AudioParam.
 ///
 /// https://webaudio.github.io/web-audio-api/#AudioParam
@@ -28,7 +39,7 @@ pub struct Param {
     summed: bool,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum ParamRate {
     /// Value is held for entire block
     KRate,
@@ -155,10 +166,40 @@ impl Param {
         self.val + self.block_mix_val
     }
 
+    /// Whether there are automation events left to run
+    pub(crate) fn has_events(&self) -> bool {
+        self.current_event < self.events.len()
+    }
+
     pub fn set_rate(&mut self, rate: ParamRate) {
         self.kind = rate;
     }
 
+    /// Save the value, rate and pending automation events of this param
+    pub(crate) fn to_preset(&self, param: ParamType, sample_rate: f32) -> ParamPreset {
+        ParamPreset {
+            param,
+            value: self.val,
+            rate: self.kind,
+            events: self.events[self.current_event..]
+                .iter()
+                .map(|e| e.to_user_event(sample_rate))
+                .collect(),
+        }
+    }
+
+    /// Replace the state of this param with the one of a preset
+    pub(crate) fn load_preset(&mut self, preset: &ParamPreset, sample_rate: f32) {
+        self.val = preset.value;
+        self.event_start_value = preset.value;
+        self.kind = preset.rate;
+        self.events.clear();
+        self.current_event = 0;
+        for event in &preset.events {
+            self.insert_event(event.to_event(sample_rate));
+        }
+    }
+
     pub(crate) fn insert_event(&mut self, event: AutomationEvent) {
         if let AutomationEvent::SetValue(val) = event {
             self.val = val;
@@ -195,6 +236,14 @@ impl Param {
         // of the current one
     }
 
+    /// Make room for the blocks of the given number of connected inputs
+    pub(crate) fn reserve_inputs(&mut self, inputs: usize) {
+        if self.blocks.capacity() < inputs {
+            let len = self.blocks.len();
+            self.blocks.reserve(inputs - len);
+        }
+    }
+
     pub(crate) fn add_block(&mut self, block: Block) {
         debug_assert!(block.chan_count() == 1);
         // summed only becomes true during a node's process() call,
@@ -208,7 +257,7 @@ impl Param {
     }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum RampKind {
     Linear,
     Exponential,
@@ -225,7 +274,7 @@ pub(crate) enum AutomationEvent {
     CancelScheduledValues(Tick),
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 /// An AutomationEvent that uses times in s instead of Ticks
 pub enum UserAutomationEvent {
     SetValue(f32),
@@ -265,6 +314,28 @@ impl UserAutomationEvent {
 }
 
 impl AutomationEvent {
+    pub(crate) fn to_user_event(self, rate: f32) -> UserAutomationEvent {
+        let rate = rate as f64;
+        match self {
+            AutomationEvent::SetValue(val) => UserAutomationEvent::SetValue(val),
+            AutomationEvent::SetValueAtTime(val, tick) => {
+                UserAutomationEvent::SetValueAtTime(val, tick / rate)
+            }
+            AutomationEvent::RampToValueAtTime(kind, val, tick) => {
+                UserAutomationEvent::RampToValueAtTime(kind, val, tick / rate)
+            }
+            AutomationEvent::SetTargetAtTime(val, start, tau) => {
+                UserAutomationEvent::SetTargetAtTime(val, start / rate, tau / rate)
+            }
+            AutomationEvent::CancelScheduledValues(t) => {
+                UserAutomationEvent::CancelScheduledValues(t / rate)
+            }
+            AutomationEvent::CancelAndHoldAtTime(t) => {
+                UserAutomationEvent::CancelAndHoldAtTime(t / rate)
+            }
+        }
+    }
+
     /// The time of the event used for ordering
     pub fn time(&self) -> Tick {
         match *self {
@@ -0,0 +1,199 @@
+//! Recycling of the storage used by blocks and chunks.
+//!
+//! Allocating on the render thread can cause glitches, so instead of
+//! freeing the buffers of dropped blocks we keep them around in a
+//! per-thread pool and hand them out again the next time a block needs
+//! storage. Once the pool has grown to the working set of the graph,
+//! rendering a block does not touch the allocator at all.
+//!
+//! When a graph is rendered on several threads, blocks produced on one
+//! thread are often dropped on another. Those threads join a shared pool
+//! which receives the storage they give back, and hands it out to any of
+//! them, so that buffers do not pile up on one thread while another keeps
+//! allocating.
+
+use block::Block;
+use smallvec::SmallVec;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of free buffers kept for each channel count.
+/// Anything beyond this is given back to the allocator.
+const MAX_FREE_BUFFERS: usize = 256;
+
+/// Maximum number of free chunk storages kept around.
+const MAX_FREE_CHUNKS: usize = 32;
+
+/// Number of blocks a pooled chunk can hold without growing.
+///
+/// This is enough for every port of a 32 channel merger or splitter.
+pub const CHUNK_CAPACITY: usize = 32;
+
+/// The storage of a chunk
+pub type ChunkStorage = SmallVec<[Block; 1]>;
+
+/// Free buffers, bucketed by the number of channels they can hold
+#[derive(Default)]
+struct Buffers(Vec<Vec<Vec<f32>>>);
+
+impl Buffers {
+    fn take(&mut self, channels: usize) -> Option<Vec<f32>> {
+        // prefer an exact fit, but a bigger buffer will do
+        for bucket in self.0.iter_mut().skip(channels) {
+            if let Some(buffer) = bucket.pop() {
+                return Some(buffer);
+            }
+        }
+        None
+    }
+
+    /// Store a buffer, freeing it if its bucket is full
+    fn put(&mut self, buffer: Vec<f32>, channels: usize) {
+        if self.0.len() <= channels {
+            let buckets = &mut self.0;
+            grow(|| buckets.resize(channels + 1, Vec::new()));
+        }
+        let bucket = &mut self.0[channels];
+        if bucket.len() < MAX_FREE_BUFFERS {
+            grow(|| bucket.reserve(1));
+            bucket.push(buffer);
+        }
+    }
+}
+
+/// Free buffers and chunk storages
+#[derive(Default)]
+struct Free {
+    buffers: Buffers,
+    chunks: Vec<ChunkStorage>,
+}
+
+impl Free {
+    fn take_chunk(&mut self) -> Option<ChunkStorage> {
+        self.chunks.pop()
+    }
+
+    /// Store an empty chunk storage, freeing it if there are enough
+    fn put_chunk(&mut self, chunk: ChunkStorage) {
+        if self.chunks.len() < MAX_FREE_CHUNKS {
+            let chunks = &mut self.chunks;
+            grow(|| chunks.reserve(1));
+            chunks.push(chunk);
+        }
+    }
+}
+
+/// Free buffers and chunk storages shared between the threads
+/// rendering a graph
+#[derive(Default)]
+pub struct SharedPool(Mutex<Free>);
+
+struct Pool {
+    free: Free,
+    shared: Option<Arc<SharedPool>>,
+}
+
+impl Pool {
+    /// Take something from the pool of this thread, or from the shared
+    /// pool if this one ran dry
+    fn take<R, F: Fn(&mut Free) -> Option<R>>(&mut self, take: F) -> Option<R> {
+        take(&mut self.free).or_else(|| {
+            self.shared
+                .as_ref()
+                .and_then(|shared| take(&mut *shared.0.lock().ok()?))
+        })
+    }
+
+    /// Give something back to the shared pool if this thread has one, or
+    /// to the pool of this thread otherwise.
+    ///
+    /// Blocks are often dropped on another thread than the one that made
+    /// them, so keeping their storage here would leave the threads making
+    /// them allocating on every block.
+    fn put<F: FnOnce(&mut Free)>(&mut self, put: F) {
+        match self.shared {
+            Some(ref shared) => {
+                if let Ok(mut shared) = shared.0.lock() {
+                    put(&mut shared)
+                }
+            }
+            None => put(&mut self.free),
+        }
+    }
+}
+
+thread_local!(static POOL: RefCell<Pool> = RefCell::new(Pool {
+    free: Free::default(),
+    shared: None,
+}));
+
+/// Make the current thread exchange buffers with `shared`, or stop
+/// doing so if `None`
+pub fn share(shared: Option<Arc<SharedPool>>) {
+    let _ = POOL.try_with(|pool| pool.borrow_mut().shared = shared);
+}
+
+/// Obtain an empty buffer with room for at least `channels` channels
+/// of `frames` frames
+pub fn take_buffer(channels: usize, frames: usize) -> Vec<f32> {
+    let buffer = POOL
+        .try_with(|pool| pool.borrow_mut().take(|free| free.buffers.take(channels)))
+        .ok()
+        .and_then(|buffer| buffer);
+    match buffer {
+        Some(mut buffer) => {
+            buffer.clear();
+            // only when blocks of different sizes share the pool
+            if buffer.capacity() < channels * frames {
+                grow(|| buffer.reserve_exact(channels * frames));
+            }
+            buffer
+        }
+        None => grow(|| Vec::with_capacity(channels * frames)),
+    }
+}
+
+/// Give a buffer back to the pool, `frames` being the number
+/// of frames of each channel of the block it belonged to
+pub fn recycle_buffer(buffer: Vec<f32>, frames: usize) {
+    let channels = buffer.capacity() / frames;
+    if channels == 0 {
+        return;
+    }
+    let _ = POOL.try_with(|pool| {
+        pool.borrow_mut()
+            .put(|free| free.buffers.put(buffer, channels))
+    });
+}
+
+/// Obtain empty storage for a chunk
+pub fn take_chunk() -> ChunkStorage {
+    POOL.try_with(|pool| pool.borrow_mut().take(Free::take_chunk))
+        .ok()
+        .and_then(|chunk| chunk)
+        .unwrap_or_else(|| grow(|| SmallVec::with_capacity(CHUNK_CAPACITY)))
+}
+
+/// Give the storage of a chunk back to the pool
+pub fn recycle_chunk(mut chunk: ChunkStorage) {
+    if !chunk.spilled() {
+        return;
+    }
+    // Dropping the blocks gives their buffers back, do this before
+    // borrowing the pool.
+    chunk.clear();
+    let _ = POOL.try_with(|pool| pool.borrow_mut().put(|free| free.put_chunk(chunk)));
+}
+
+/// The pool is allowed to allocate while rendering, but only while
+/// the graph warms up, to grow to its working set.
+#[cfg(feature = "alloc_check")]
+fn grow<R, F: FnOnce() -> R>(f: F) -> R {
+    ::alloc_check::grow(f)
+}
+
+#[cfg(not(feature = "alloc_check"))]
+#[inline]
+fn grow<R, F: FnOnce() -> R>(f: F) -> R {
+    f()
+}
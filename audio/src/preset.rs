@@ -0,0 +1,67 @@
+//! Presets, for saving an audio graph and restoring it later.
+//!
+//! A preset holds the options each node was created with, its channel
+//! configuration, the state of its params including the automation events
+//! still to come, and the connections between the nodes. Presets are plain
+//! serde types, so they can be stored in whatever format the embedder likes.
+//!
+//! Anything that is not part of how the graph is set up is left out, like
+//! when source nodes were scheduled to start or how far into their buffer
+//! they are.
+
+use channel_layout::MixingMatrix;
+use graph::{InputPort, NodeId, PortIndex};
+use node::{AudioNodeInit, ChannelInfo};
+use param::{ParamRate, ParamType, UserAutomationEvent};
+
+/// A param of a node
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParamPreset {
+    pub param: ParamType,
+    /// The value of the param, not including connected inputs
+    pub value: f32,
+    pub rate: ParamRate,
+    /// The automation events that have not yet run, with
+    /// times relative to the start of the context
+    pub events: Vec<UserAutomationEvent>,
+}
+
+/// A node of the graph
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodePreset {
+    /// The id of the node in the graph the preset was taken from,
+    /// used for referring to it from connections
+    pub id: NodeId,
+    pub init: AudioNodeInit,
+    pub channel_info: ChannelInfo,
+    /// See `AudioGraph::set_mixing_matrix`
+    #[serde(default)]
+    pub mixing: Option<MixingMatrix>,
+    pub params: Vec<ParamPreset>,
+}
+
+/// A connection from an output port to an input port or a param
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ConnectionPreset {
+    pub from: NodeId,
+    pub output: u32,
+    pub to: NodeId,
+    pub input: PortIndex<InputPort>,
+}
+
+/// The setup of a whole audio graph.
+///
+/// The destination node is part of the preset, and is
+/// restored onto the destination of the graph loading it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GraphPreset {
+    pub nodes: Vec<NodePreset>,
+    pub connections: Vec<ConnectionPreset>,
+}
+
+impl GraphPreset {
+    /// Find a node by its id
+    pub fn node(&self, id: NodeId) -> Option<&NodePreset> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+}
@@ -0,0 +1,166 @@
+//! Rendering driven by the caller instead of a sink.
+//!
+//! Plugin hosts and game engines own the audio callback and ask for frames
+//! when they need them. `AudioContext::new_pull` makes a context whose
+//! audio is rendered by a `PullRenderer` from within such a callback.
+//!
+//! Until the renderer starts, the messages of the context are handled right
+//! away on the control thread, so that the graph can be built before the
+//! host starts calling back. Once it has started, they are handled by the
+//! renderer between render quanta.
+
+use block::Block;
+use context::ProcessingState;
+use control_queue::ControlReceiver;
+use node::ChannelInterpretation;
+use render_thread::AudioRenderThread;
+use std::sync::{Arc, Mutex};
+use AudioBackend;
+
+/// The state of the rendering, and the end of the queue its messages
+/// come from
+struct PullState<B: AudioBackend> {
+    thread: AudioRenderThread<B>,
+    /// Dropped once the context is closed, so that later calls
+    /// of the context fail rather than wait for an answer
+    event_queue: Option<ControlReceiver>,
+}
+
+impl<B: AudioBackend + 'static> PullState<B> {
+    /// Handle the pending messages, all of them if `all` is set, or as
+    /// many as fit in the budget of a render quantum otherwise
+    fn handle_messages(&mut self, all: bool) {
+        let closed = match self.event_queue {
+            Some(ref event_queue) if all => self.thread.handle_messages(event_queue),
+            Some(ref event_queue) => self.thread.drain_messages(event_queue),
+            None => return,
+        };
+        if closed {
+            self.event_queue = None;
+        }
+    }
+}
+
+pub struct PullRenderer<B: AudioBackend> {
+    /// Shared with the control thread until the renderer starts
+    waiting: Arc<Mutex<Option<PullState<B>>>>,
+    /// Taken from `waiting` when the renderer starts
+    state: Option<PullState<B>>,
+    sample_rate: f32,
+    render_size: usize,
+    /// The last render quantum, if it was not handed out entirely,
+    /// and the first of its frames left to hand out
+    pending: Option<(Block, usize)>,
+}
+
+impl<B: AudioBackend + 'static> PullRenderer<B> {
+    pub(crate) fn new(thread: AudioRenderThread<B>, event_queue: ControlReceiver) -> Self {
+        Self {
+            sample_rate: thread.sample_rate,
+            render_size: thread.render_size,
+            waiting: Arc::new(Mutex::new(Some(PullState {
+                thread,
+                event_queue: Some(event_queue),
+            }))),
+            state: None,
+            pending: None,
+        }
+    }
+
+    /// What the context calls after sending a message, to have it
+    /// handled right away until the renderer starts
+    pub(crate) fn handler(&self) -> Box<Fn() + Send>
+    where
+        B::Sink: Send,
+    {
+        let waiting = self.waiting.clone();
+        Box::new(move || {
+            if let Some(ref mut state) = *waiting.lock().unwrap() {
+                state.handle_messages(true);
+            }
+        })
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Start the renderer, if it has not started yet. From then on, the
+    /// messages of the context are only handled by `render_into`.
+    ///
+    /// This is done by the first call to `render_into`, but it may have to
+    /// wait for the control thread to finish handling a message. Calling
+    /// this before the host starts calling back avoids that.
+    pub fn prepare(&mut self) {
+        if self.state.is_none() {
+            self.state = self.waiting.lock().unwrap().take();
+        }
+    }
+
+    /// Fill `out` with interleaved frames of `channels` channels, rendering
+    /// as many render quanta as needed. Frames of the last quantum that do
+    /// not fit are kept for the next call.
+    ///
+    /// The messages of the context are handled before each render quantum.
+    /// Once the renderer has started, the calls of the context waiting on an
+    /// answer, like `create_node`, only return once this is called, so they
+    /// must not be made from the thread calling this. While the context is
+    /// suspended or once it is closed, `out` is filled with silence.
+    pub fn render_into(&mut self, out: &mut [f32], channels: u8) {
+        assert!(channels > 0, "Rendering needs at least one channel");
+        self.prepare();
+        if let Some(ref state) = self.state {
+            // the host may call back from another thread than last time
+            state.thread.graph.join_workers();
+        }
+
+        let channels = channels as usize;
+        let frames = out.len() / channels;
+        let mut written = 0;
+        while written < frames {
+            let (mut block, offset) = match self.pending.take() {
+                Some(pending) => pending,
+                None => match self.render_quantum() {
+                    Some(block) => (block, 0),
+                    None => break,
+                },
+            };
+            if block.chan_count() as usize != channels {
+                block.mix(channels as u8, ChannelInterpretation::Speakers);
+            }
+            block.explicit_silence();
+            let end = self.render_size.min(offset + frames - written);
+            for chan in 0..channels {
+                let data = &block.data_chan(chan as u8)[offset..end];
+                let out = out[written * channels + chan..].iter_mut().step_by(channels);
+                for (o, sample) in out.zip(data) {
+                    *o = *sample;
+                }
+            }
+            written += end - offset;
+            if end < self.render_size {
+                self.pending = Some((block, end));
+            }
+        }
+        for sample in &mut out[written * channels..] {
+            *sample = 0.;
+        }
+    }
+
+    /// Handle the pending messages and render a quantum, unless the
+    /// context is suspended or closed
+    fn render_quantum(&mut self) -> Option<Block> {
+        let state = self.state.as_mut()?;
+        state.handle_messages(false);
+        if state.thread.state != ProcessingState::Running {
+            return None;
+        }
+        let mut chunk = state.thread.process();
+        state.thread.advance();
+        if chunk.len() == 0 {
+            Some(Block::new(self.render_size))
+        } else {
+            Some(chunk.blocks[0].take())
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Measuring how much of its real-time budget the render thread uses.
+//!
+//! https://webaudio.github.io/web-audio-api/#AudioRenderCapacity
+
+use std::time::Duration;
+
+/// The load of the render thread over a period of time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderCapacityStats {
+    /// Mean over the rendered quanta of the time taken to render a quantum,
+    /// relative to the duration of the audio it holds. Over 1 means we are
+    /// rendering slower than real time.
+    pub average_load: f64,
+    /// Highest load of a single quantum
+    pub peak_load: f64,
+    /// Number of times the sink reported running out of audio
+    pub underruns: u64,
+    /// Number of quanta rendered
+    pub quanta: u64,
+}
+
+impl RenderCapacityStats {
+    /// Underruns relative to the number of quanta rendered
+    pub fn underrun_ratio(&self) -> f64 {
+        if self.quanta == 0 {
+            return 0.;
+        }
+        self.underruns as f64 / self.quanta as f64
+    }
+}
+
+/// Accumulates the load of the render thread until asked for stats
+#[derive(Default)]
+pub(crate) struct RenderCapacity {
+    total_load: f64,
+    peak_load: f64,
+    underruns: u64,
+    quanta: u64,
+}
+
+impl RenderCapacity {
+    /// Record that rendering a quantum of `budget` seconds took `elapsed`
+    pub fn record(&mut self, elapsed: Duration, budget: f64) {
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+        let load = elapsed / budget;
+        self.total_load += load;
+        if load > self.peak_load {
+            self.peak_load = load;
+        }
+        self.quanta += 1;
+    }
+
+    pub fn underrun(&mut self) {
+        self.underruns += 1;
+    }
+
+    /// The stats since the previous call
+    pub fn take(&mut self) -> RenderCapacityStats {
+        let stats = RenderCapacityStats {
+            average_load: if self.quanta > 0 {
+                self.total_load / self.quanta as f64
+            } else {
+                0.
+            },
+            peak_load: self.peak_load,
+            underruns: self.underruns,
+            quanta: self.quanta,
+        };
+        *self = Default::default();
+        stats
+    }
+}
@@ -1,27 +1,51 @@
-use block::{Chunk, Tick, FRAMES_PER_BLOCK};
-use buffer_source_node::AudioBufferSourceNode;
+use block::{Block, Chunk, Tick};
+use buffer_source_node::{AudioBuffer, AudioBufferSourceNode};
+use channel_layout::MixingMatrix;
 use channel_node::{ChannelMergerNode, ChannelSplitterNode};
-use context::{AudioContextOptions, ProcessingState, StateChangeResult};
+use context::{AudioContextOptions, ProcessingState, SharedState, StateChangeResult};
+use control_queue::ControlReceiver;
 use destination_node::DestinationNode;
+use error::{AudioError, AudioResult};
 use gain_node::GainNode;
-use graph::{AudioGraph, InputPort, NodeId, OutputPort, PortId};
+use graph::{AudioGraph, Disconnection, InputPort, NodeId, NodeProfile, OutputPort, PortId};
+use graph::{check_mixing_matrix, PortIndex};
 use node::BlockInfo;
-use node::{AudioNodeEngine, AudioNodeInit, AudioNodeMessage};
+use node::{AudioNodeEngine, AudioNodeInit, AudioNodeMessage, MAX_CHANNEL_COUNT};
 use offline_sink::OfflineAudioSink;
 use oscillator_node::OscillatorNode;
+use preset::{GraphPreset, NodePreset};
+use render_capacity::{RenderCapacity, RenderCapacityStats};
 use sink::AudioSink;
-use std::sync::mpsc::{Receiver, Sender};
+use snapshot::GraphSnapshot;
+use std::boxed::FnBox;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use AudioBackend;
 
 pub enum AudioRenderThreadMsg {
-    CreateNode(AudioNodeInit, Sender<NodeId>),
-    ConnectPorts(PortId<OutputPort>, PortId<InputPort>),
-    MessageNode(NodeId, AudioNodeMessage),
+    CreateNode(AudioNodeInit, Sender<AudioResult<NodeId>>),
+    ConnectPorts(PortId<OutputPort>, PortId<InputPort>, Sender<AudioResult<()>>),
+    /// Only failures are sent back
+    MessageNode(NodeId, AudioNodeMessage, Sender<AudioResult<()>>),
+    SetMixingMatrix(NodeId, Option<MixingMatrix>, Sender<AudioResult<()>>),
     Resume(Sender<StateChangeResult>),
     Suspend(Sender<StateChangeResult>),
     Close(Sender<StateChangeResult>),
     SinkNeedData,
+    /// Sent when there are messages waiting in the lock-free control queue
+    Wake,
+    /// Sent by the sink when it runs out of audio to play,
+    /// counted in the stats of `AudioContext::render_capacity`
+    SinkUnderrun,
+    GetRenderCapacity(Sender<RenderCapacityStats>),
+    SetProfiling(bool),
+    GetNodeProfile(NodeId, Sender<AudioResult<NodeProfile>>),
     GetCurrentTime(Sender<f64>),
+    GetGraphSnapshot(Sender<GraphSnapshot>),
+    SavePreset(Sender<GraphPreset>),
+    LoadPreset(GraphPreset, Sender<AudioResult<HashMap<NodeId, NodeId>>>),
 
     DisconnectAllFrom(NodeId),
     DisconnectOutput(PortId<OutputPort>),
@@ -30,12 +54,27 @@ pub enum AudioRenderThreadMsg {
     DisconnectOutputBetween(PortId<OutputPort>, NodeId),
     DisconnectOutputBetweenTo(PortId<OutputPort>, PortId<InputPort>),
 
+    ConnectPortsAt(f64, PortId<OutputPort>, PortId<InputPort>, Sender<AudioResult<()>>),
+    DisconnectAt(f64, Disconnection),
+    /// Start rendering an offline context, sending the audio once done
+    StartRendering(Sender<AudioBuffer>, Sender<AudioResult<()>>),
+    /// Suspend an offline context at the given time, calling back
+    /// on the render thread when it happens
+    SuspendAt(f64, Box<FnBox() + Send + 'static>, Sender<AudioResult<()>>),
+    /// Call back whenever the state of the context changes
+    OnStateChange(Box<Fn(ProcessingState) + Send + 'static>),
+
     SetSinkEosCallback(Box<Fn(Box<AsRef<[f32]>>) + Send + Sync + 'static>),
+
+    /// Messages to handle all at once, between two render quanta
+    Batch(Vec<AudioRenderThreadMsg>, Sender<()>),
 }
 
 pub enum Sink<B: AudioBackend> {
     RealTime(B::Sink),
     Offline(OfflineAudioSink),
+    /// The audio is pulled with `PullRenderer::render_into`
+    Pull,
 }
 
 impl<B: AudioBackend> AudioSink for Sink<B> {
@@ -43,6 +82,7 @@ impl<B: AudioBackend> AudioSink for Sink<B> {
         match *self {
             Sink::RealTime(ref sink) => sink.init(sample_rate, sender),
             Sink::Offline(ref sink) => sink.init(sample_rate, sender),
+            Sink::Pull => Ok(()),
         }
     }
 
@@ -50,6 +90,7 @@ impl<B: AudioBackend> AudioSink for Sink<B> {
         match *self {
             Sink::RealTime(ref sink) => sink.play(),
             Sink::Offline(ref sink) => sink.play(),
+            Sink::Pull => Ok(()),
         }
     }
 
@@ -57,6 +98,7 @@ impl<B: AudioBackend> AudioSink for Sink<B> {
         match *self {
             Sink::RealTime(ref sink) => sink.stop(),
             Sink::Offline(ref sink) => sink.stop(),
+            Sink::Pull => Ok(()),
         }
     }
 
@@ -64,6 +106,7 @@ impl<B: AudioBackend> AudioSink for Sink<B> {
         match *self {
             Sink::RealTime(ref sink) => sink.has_enough_data(),
             Sink::Offline(ref sink) => sink.has_enough_data(),
+            Sink::Pull => false,
         }
     }
 
@@ -71,6 +114,24 @@ impl<B: AudioBackend> AudioSink for Sink<B> {
         match *self {
             Sink::RealTime(ref sink) => sink.push_data(chunk),
             Sink::Offline(ref sink) => sink.push_data(chunk),
+            Sink::Pull => Err(()),
+        }
+    }
+
+    fn latency(&self) -> f64 {
+        match *self {
+            Sink::RealTime(ref sink) => sink.latency(),
+            Sink::Offline(ref sink) => sink.latency(),
+            // the host knows better
+            Sink::Pull => 0.,
+        }
+    }
+
+    fn position(&self) -> Option<f64> {
+        match *self {
+            Sink::RealTime(ref sink) => sink.position(),
+            Sink::Offline(ref sink) => sink.position(),
+            Sink::Pull => None,
         }
     }
 
@@ -78,161 +139,542 @@ impl<B: AudioBackend> AudioSink for Sink<B> {
         match *self {
             Sink::RealTime(ref sink) => sink.set_eos_callback(callback),
             Sink::Offline(ref sink) => sink.set_eos_callback(callback),
+            Sink::Pull => {}
         }
     }
 }
 
+/// A change to the connections of the graph, scheduled for a later time
+enum ScheduledChange {
+    Connect(PortId<OutputPort>, PortId<InputPort>),
+    Disconnect(Disconnection),
+}
+
+/// Make the engine of a node, failing for the nodes we do not support yet
+pub(crate) fn make_node(node_type: AudioNodeInit) -> AudioResult<Box<AudioNodeEngine>> {
+    match node_type {
+        AudioNodeInit::ChannelMergerNode(ref options) |
+        AudioNodeInit::ChannelSplitterNode(ref options)
+            if options.channels == 0 || options.channels > MAX_CHANNEL_COUNT =>
+        {
+            return Err(AudioError::IndexSize)
+        }
+        _ => {}
+    }
+    let node: Box<AudioNodeEngine> = match node_type {
+        AudioNodeInit::AudioBufferSourceNode(options) => Box::new(AudioBufferSourceNode::new(options)),
+        AudioNodeInit::DestinationNode => Box::new(DestinationNode::new()),
+        AudioNodeInit::GainNode(options) => Box::new(GainNode::new(options)),
+        AudioNodeInit::OscillatorNode(options) => Box::new(OscillatorNode::new(options)),
+        AudioNodeInit::ChannelMergerNode(options) => Box::new(ChannelMergerNode::new(options)),
+        AudioNodeInit::ChannelSplitterNode(options) => Box::new(ChannelSplitterNode::new(options)),
+        _ => return Err(AudioError::NotSupported),
+    };
+    Ok(node)
+}
+
+/// Set up the channels and params of a node as in its preset
+fn load_node_preset(
+    engine: &mut AudioNodeEngine,
+    node: &NodePreset,
+    sample_rate: f32,
+) -> AudioResult<()> {
+    let info = node.channel_info;
+    if engine.channel_count() != info.count {
+        engine.set_channel_count(info.count)?;
+    }
+    if engine.channel_count_mode() != info.mode {
+        engine.set_channel_count_mode(info.mode)?;
+    }
+    if engine.channel_interpretation() != info.interpretation {
+        engine.set_channel_interpretation(info.interpretation)?;
+    }
+    for param in &node.params {
+        engine.get_param(param.param)?.load_preset(param, sample_rate);
+    }
+    Ok(())
+}
+
 pub struct AudioRenderThread<B: AudioBackend> {
     pub graph: AudioGraph,
     pub sink: Sink<B>,
     pub state: ProcessingState,
+    /// What the control thread reads without asking
+    shared: Arc<Mutex<SharedState>>,
+    state_callbacks: Vec<Box<Fn(ProcessingState) + Send + 'static>>,
     pub sample_rate: f32,
+    /// Number of frames in a render quantum
+    pub render_size: usize,
     pub current_time: f64,
     pub current_frame: Tick,
+    /// Changes waiting for their time to come, in order
+    scheduled_changes: Vec<(Tick, ScheduledChange)>,
+    /// Offline suspends waiting for their time to come, in order
+    scheduled_suspends: Vec<(Tick, Box<FnBox() + Send + 'static>)>,
+    render_capacity: RenderCapacity,
 }
 
 impl<B: AudioBackend + 'static> AudioRenderThread<B> {
     /// Start the audio render thread
-    pub fn start(
-        event_queue: Receiver<AudioRenderThreadMsg>,
+    pub(crate) fn start(
+        event_queue: ControlReceiver,
         sender: Sender<AudioRenderThreadMsg>,
         sample_rate: f32,
         graph: AudioGraph,
         options: AudioContextOptions,
+        shared: Arc<Mutex<SharedState>>,
     ) -> Result<(), ()> {
+        let (render_threads, render_size) = match options {
+            AudioContextOptions::RealTimeAudioContext(ref options) => {
+                (options.render_threads, options.render_size)
+            }
+            AudioContextOptions::OfflineAudioContext(ref options) => {
+                (options.render_threads, options.render_size)
+            }
+        };
         let sink = match options {
             AudioContextOptions::RealTimeAudioContext(_) => Sink::RealTime(B::make_sink()?),
             AudioContextOptions::OfflineAudioContext(options) => Sink::Offline(
-                OfflineAudioSink::new(options.channels as usize, options.length),
+                OfflineAudioSink::new(options.channels as usize, options.length, options.output),
             ),
         };
 
-        let mut graph = Self {
+        let mut graph = Self::new(graph, sink, sample_rate, render_size, shared);
+
+        // The workers need to be started from this thread,
+        // so that it shares its block buffers with them
+        graph.graph.set_render_threads(render_threads);
+
+        graph.sink.init(sample_rate, sender)?;
+        graph.event_loop(event_queue);
+
+        Ok(())
+    }
+
+    pub(crate) fn new(
+        graph: AudioGraph,
+        sink: Sink<B>,
+        sample_rate: f32,
+        render_size: usize,
+        shared: Arc<Mutex<SharedState>>,
+    ) -> Self {
+        Self {
             graph,
             sink,
             state: ProcessingState::Suspended,
+            shared,
+            state_callbacks: Vec::new(),
             sample_rate,
+            render_size,
             current_time: 0.,
             current_frame: Tick(0),
-        };
-
-        graph.sink.init(sample_rate, sender)?;
-        graph.event_loop(event_queue);
+            scheduled_changes: Vec::new(),
+            scheduled_suspends: Vec::new(),
+            render_capacity: Default::default(),
+        }
+    }
 
-        Ok(())
+    /// Set the state, letting the control thread know
+    fn set_state(&mut self, state: ProcessingState) {
+        if self.state == state {
+            return;
+        }
+        self.state = state;
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.state = state;
+            if state == ProcessingState::Running {
+                shared.output_latency = self.sink.latency();
+            }
+        }
+        for callback in &self.state_callbacks {
+            callback(state);
+        }
     }
 
     make_render_thread_state_change!(resume, Running, play);
 
     make_render_thread_state_change!(suspend, Suspended, stop);
 
-    fn create_node(&mut self, node_type: AudioNodeInit) -> NodeId {
-        let node: Box<AudioNodeEngine> = match node_type {
-            AudioNodeInit::AudioBufferSourceNode(options) => {
-                Box::new(AudioBufferSourceNode::new(options))
+    /// Move past a render quantum handed to the sink,
+    /// letting the control thread know
+    pub(crate) fn advance(&mut self) {
+        let frames = Tick(self.render_size as u64);
+        self.current_frame += frames;
+        self.current_time = self.current_frame / self.sample_rate as f64;
+        // asking the sink is not free, so only do it every second or so
+        let latency = if self.current_frame.0 % (self.sample_rate as u64) < frames.0 {
+            Some(self.sink.latency())
+        } else {
+            None
+        };
+        let position = self.sink.position();
+        let now = Instant::now();
+        // the control thread only holds the lock briefly,
+        // and missing an update is harmless
+        if let Ok(mut shared) = self.shared.try_lock() {
+            shared.rendered_time = self.current_time;
+            shared.rendered_at = now;
+            if let Some(latency) = latency {
+                shared.output_latency = latency;
             }
-            AudioNodeInit::DestinationNode => Box::new(DestinationNode::new()),
-            AudioNodeInit::GainNode(options) => Box::new(GainNode::new(options)),
-            AudioNodeInit::OscillatorNode(options) => Box::new(OscillatorNode::new(options)),
-            AudioNodeInit::ChannelMergerNode(options) => Box::new(ChannelMergerNode::new(options)),
-            AudioNodeInit::ChannelSplitterNode(options) => {
-                Box::new(ChannelSplitterNode::new(options))
+            if let Some(position) = position {
+                shared.output_position = Some((position, now));
             }
-            _ => unimplemented!(),
-        };
-        self.graph.add_node(node)
+        }
     }
 
-    fn connect_ports(&mut self, output: PortId<OutputPort>, input: PortId<InputPort>) {
+    fn create_node(&mut self, node_type: AudioNodeInit) -> AudioResult<NodeId> {
+        let node = make_node(node_type)?;
+        Ok(self.graph.add_node(node))
+    }
+
+    /// Add the nodes and connections of a preset to the graph, returning
+    /// the id each node of the preset got. The destination of the preset
+    /// is mapped to the destination of the graph.
+    ///
+    /// The nodes are set up and the connections checked before anything
+    /// is added to the graph, so a preset that fails to load leaves the
+    /// graph as it was.
+    fn load_preset(&mut self, preset: GraphPreset) -> AudioResult<HashMap<NodeId, NodeId>> {
+        // the destination is set up on a stand-in first, as it is
+        // already part of the graph
+        let mut engines = Vec::with_capacity(preset.nodes.len());
+        for node in &preset.nodes {
+            let mut engine = match node.init {
+                AudioNodeInit::DestinationNode => {
+                    Box::new(DestinationNode::new()) as Box<AudioNodeEngine>
+                }
+                ref init => make_node(init.clone())?,
+            };
+            load_node_preset(&mut *engine, node, self.sample_rate)?;
+            if let Some(ref matrix) = node.mixing {
+                check_mixing_matrix(&*engine, matrix)?;
+            }
+            engines.push(engine);
+        }
+        for conn in &preset.connections {
+            let from = preset.nodes.iter().position(|node| node.id == conn.from);
+            let to = preset.nodes.iter().position(|node| node.id == conn.to);
+            let (from, to) = match (from, to) {
+                (Some(from), Some(to)) => (from, to),
+                _ => return Err(AudioError::InvalidAccess),
+            };
+            if conn.output >= engines[from].output_count() {
+                return Err(AudioError::IndexSize);
+            }
+            match conn.input {
+                PortIndex::Port(input) => {
+                    if input >= engines[to].input_count() {
+                        return Err(AudioError::IndexSize);
+                    }
+                }
+                PortIndex::Param(param) => {
+                    engines[to].get_param(param)?;
+                }
+            }
+        }
+
+        // nothing below fails, having been checked above
+        let mut ids = HashMap::new();
+        for (node, engine) in preset.nodes.iter().zip(engines) {
+            let id = match node.init {
+                AudioNodeInit::DestinationNode => {
+                    let dest = self.graph.dest_id();
+                    load_node_preset(&mut **self.graph.node_mut(dest)?, node, self.sample_rate)?;
+                    dest
+                }
+                _ => self.graph.add_node(engine),
+            };
+            self.graph.set_mixing_matrix(id, node.mixing.clone())?;
+            ids.insert(node.id, id);
+        }
+        for conn in &preset.connections {
+            let output = ids[&conn.from].output(conn.output);
+            let input = ids[&conn.to].port(conn.input);
+            self.graph.add_edge(output, input)?;
+        }
+
+        Ok(ids)
+    }
+
+    fn connect_ports(
+        &mut self,
+        output: PortId<OutputPort>,
+        input: PortId<InputPort>,
+    ) -> AudioResult<()> {
         self.graph.add_edge(output, input)
     }
 
-    fn process(&mut self) -> Chunk {
+    fn schedule_change(&mut self, time: f64, change: ScheduledChange) {
+        let tick = Tick::from_time(time, self.sample_rate);
+        // after the changes scheduled for the same time, to keep them in order
+        let idx = self
+            .scheduled_changes
+            .iter()
+            .position(|&(t, _)| t > tick)
+            .unwrap_or(self.scheduled_changes.len());
+        self.scheduled_changes.insert(idx, (tick, change));
+    }
+
+    /// Apply the scheduled changes falling within the next block.
+    /// They are crossfaded over that block to avoid clicks.
+    fn apply_scheduled_changes(&mut self) {
+        let end = self.current_frame + self.render_size as u64;
+        let due = self
+            .scheduled_changes
+            .iter()
+            .take_while(|&&(tick, _)| tick < end)
+            .count();
+        for (_, change) in self.scheduled_changes.drain(..due) {
+            match change {
+                ScheduledChange::Connect(output, input) => {
+                    // the ports were checked when scheduling
+                    let _ = self.graph.add_edge_with_fade(output, input);
+                }
+                ScheduledChange::Disconnect(disconnection) => {
+                    self.graph.disconnect_with_fade(disconnection)
+                }
+            }
+        }
+    }
+
+    fn start_rendering(&mut self, sender: Sender<AudioBuffer>) -> AudioResult<()> {
+        match self.sink {
+            Sink::Offline(ref sink) => sink.set_rendering(sender)?,
+            Sink::RealTime(_) | Sink::Pull => return Err(AudioError::InvalidState),
+        }
+        self.resume().map_err(|_| AudioError::InvalidState)
+    }
+
+    fn suspend_at(
+        &mut self,
+        time: f64,
+        callback: Box<FnBox() + Send + 'static>,
+    ) -> AudioResult<()> {
+        let length = match self.sink {
+            Sink::Offline(ref sink) => sink.length(),
+            Sink::RealTime(_) | Sink::Pull => return Err(AudioError::InvalidState),
+        };
+        if !time.is_finite() || time < 0. {
+            return Err(AudioError::InvalidState);
+        }
+        // checked before converting, floats out of range
+        // do not convert to integers reliably
+        let frame = (time * self.sample_rate as f64).ceil();
+        if frame >= length as f64 {
+            return Err(AudioError::InvalidState);
+        }
+        // rounded up to a render quantum
+        let frames = self.render_size as u64;
+        let frame = frame as u64;
+        let tick = Tick((frame + frames - 1) / frames * frames);
+        if tick < self.current_frame || tick.0 >= length as u64 {
+            return Err(AudioError::InvalidState);
+        }
+        match self.scheduled_suspends.binary_search_by_key(&tick, |&(t, _)| t) {
+            Ok(_) => Err(AudioError::InvalidState),
+            Err(idx) => {
+                self.scheduled_suspends.insert(idx, (tick, callback));
+                Ok(())
+            }
+        }
+    }
+
+    /// Suspend if rendering has reached the next scheduled suspend,
+    /// returning whether it did
+    fn reach_suspend(&mut self) -> bool {
+        match self.scheduled_suspends.first() {
+            Some(&(tick, _)) if tick <= self.current_frame => (),
+            _ => return false,
+        }
+        let (_, callback) = self.scheduled_suspends.remove(0);
+        let _ = self.suspend();
+        callback();
+        true
+    }
+
+    pub(crate) fn process(&mut self) -> Chunk {
+        if !self.scheduled_changes.is_empty() {
+            self.apply_scheduled_changes();
+        }
         let info = BlockInfo {
             sample_rate: self.sample_rate,
             frame: self.current_frame,
             time: self.current_time,
+            frames: self.render_size,
         };
-        self.graph.process(&info)
+        let start = Instant::now();
+        let mut chunk = self.graph.process(&info);
+        let budget = self.render_size as f64 / self.sample_rate as f64;
+        self.render_capacity.record(start.elapsed(), budget);
+        // sinks can count on a block to get the render size from
+        if chunk.len() == 0 {
+            chunk.blocks.push(Block::new(self.render_size));
+        }
+        chunk
     }
 
-    fn event_loop(&mut self, event_queue: Receiver<AudioRenderThreadMsg>) {
-        let sample_rate = self.sample_rate;
-        let handle_msg = move |context: &mut Self, msg: AudioRenderThreadMsg| -> bool {
-            let mut break_loop = false;
-            match msg {
-                AudioRenderThreadMsg::CreateNode(node_type, tx) => {
-                    let _ = tx.send(context.create_node(node_type));
-                }
-                AudioRenderThreadMsg::ConnectPorts(output, input) => {
-                    context.connect_ports(output, input);
-                }
-                AudioRenderThreadMsg::Resume(tx) => {
-                    let _ = tx.send(context.resume());
-                }
-                AudioRenderThreadMsg::Suspend(tx) => {
-                    let _ = tx.send(context.suspend());
-                }
-                AudioRenderThreadMsg::Close(tx) => {
-                    let _ = tx.send(context.suspend());
-                    break_loop = true;
-                }
-                AudioRenderThreadMsg::GetCurrentTime(response) => {
-                    response.send(context.current_time).unwrap()
-                }
-                AudioRenderThreadMsg::MessageNode(id, msg) => {
-                    context.graph.node_mut(id).message(msg, sample_rate)
-                }
-                AudioRenderThreadMsg::SinkNeedData => {
-                    // Do nothing. This will simply unblock the thread so we
-                    // can restart the non-blocking event loop.
-                }
-                AudioRenderThreadMsg::DisconnectAllFrom(id) => {
-                    context.graph.disconnect_all_from(id)
-                }
-                AudioRenderThreadMsg::DisconnectOutput(out) => context.graph.disconnect_output(out),
-                AudioRenderThreadMsg::DisconnectBetween(from, to) => {
-                    context.graph.disconnect_between(from, to)
-                }
-                AudioRenderThreadMsg::DisconnectTo(from, to) => {
-                    context.graph.disconnect_to(from, to)
-                }
-                AudioRenderThreadMsg::DisconnectOutputBetween(from, to) => {
-                    context.graph.disconnect_output_between(from, to)
+    /// Handle a message from the control thread or the sink,
+    /// returning true if the render thread should shut down
+    fn handle_msg(&mut self, msg: AudioRenderThreadMsg) -> bool {
+        let mut break_loop = false;
+        match msg {
+            AudioRenderThreadMsg::CreateNode(node_type, tx) => {
+                let _ = tx.send(self.create_node(node_type));
+            }
+            AudioRenderThreadMsg::ConnectPorts(output, input, tx) => {
+                let _ = tx.send(self.connect_ports(output, input));
+            }
+            AudioRenderThreadMsg::Resume(tx) => {
+                let _ = tx.send(self.resume());
+            }
+            AudioRenderThreadMsg::Suspend(tx) => {
+                let _ = tx.send(self.suspend());
+            }
+            AudioRenderThreadMsg::Close(tx) => {
+                let result = self.sink.stop();
+                self.set_state(ProcessingState::Closed);
+                let _ = tx.send(result);
+                break_loop = true;
+            }
+            AudioRenderThreadMsg::GetCurrentTime(response) => {
+                response.send(self.current_time).unwrap()
+            }
+            AudioRenderThreadMsg::GetGraphSnapshot(response) => {
+                let _ = response.send(self.graph.snapshot());
+            }
+            AudioRenderThreadMsg::SavePreset(response) => {
+                let _ = response.send(self.graph.save_preset(self.sample_rate));
+            }
+            AudioRenderThreadMsg::LoadPreset(preset, response) => {
+                let _ = response.send(self.load_preset(preset));
+            }
+            AudioRenderThreadMsg::MessageNode(id, msg, tx) => {
+                let result = self
+                    .graph
+                    .node_mut(id)
+                    .and_then(|mut node| node.message(msg, self.sample_rate));
+                if result.is_err() {
+                    let _ = tx.send(result);
                 }
-                AudioRenderThreadMsg::DisconnectOutputBetweenTo(from, to) => {
-                    context.graph.disconnect_output_between_to(from, to)
+            }
+            AudioRenderThreadMsg::SetMixingMatrix(id, matrix, tx) => {
+                let _ = tx.send(self.graph.set_mixing_matrix(id, matrix));
+            }
+            AudioRenderThreadMsg::SinkNeedData | AudioRenderThreadMsg::Wake => {
+                // Do nothing. This will simply unblock the thread so we
+                // can restart the non-blocking event loop.
+            }
+            AudioRenderThreadMsg::DisconnectAllFrom(id) => {
+                self.graph.disconnect_all_from(id)
+            }
+            AudioRenderThreadMsg::DisconnectOutput(out) => self.graph.disconnect_output(out),
+            AudioRenderThreadMsg::DisconnectBetween(from, to) => {
+                self.graph.disconnect_between(from, to)
+            }
+            AudioRenderThreadMsg::DisconnectTo(from, to) => {
+                self.graph.disconnect_to(from, to)
+            }
+            AudioRenderThreadMsg::DisconnectOutputBetween(from, to) => {
+                self.graph.disconnect_output_between(from, to)
+            }
+            AudioRenderThreadMsg::DisconnectOutputBetweenTo(from, to) => {
+                self.graph.disconnect_output_between_to(from, to)
+            }
+            AudioRenderThreadMsg::ConnectPortsAt(time, output, input, tx) => {
+                let result = self.graph.check_ports(output, input);
+                if result.is_ok() {
+                    self.schedule_change(time, ScheduledChange::Connect(output, input));
                 }
-                AudioRenderThreadMsg::SetSinkEosCallback(callback) => {
-                    context.sink.set_eos_callback(callback);
+                let _ = tx.send(result);
+            }
+            AudioRenderThreadMsg::StartRendering(sender, tx) => {
+                let _ = tx.send(self.start_rendering(sender));
+            }
+            AudioRenderThreadMsg::SuspendAt(time, callback, tx) => {
+                let _ = tx.send(self.suspend_at(time, callback));
+            }
+            AudioRenderThreadMsg::DisconnectAt(time, disconnection) => {
+                self.schedule_change(time, ScheduledChange::Disconnect(disconnection))
+            }
+            AudioRenderThreadMsg::SinkUnderrun => self.render_capacity.underrun(),
+            AudioRenderThreadMsg::GetRenderCapacity(response) => {
+                let _ = response.send(self.render_capacity.take());
+            }
+            AudioRenderThreadMsg::SetProfiling(profiling) => self.graph.set_profiling(profiling),
+            AudioRenderThreadMsg::GetNodeProfile(id, response) => {
+                let _ = response.send(self.graph.node_profile(id));
+            }
+            AudioRenderThreadMsg::SetSinkEosCallback(callback) => {
+                self.sink.set_eos_callback(callback);
+            }
+            AudioRenderThreadMsg::OnStateChange(callback) => {
+                self.state_callbacks.push(callback);
+            }
+            AudioRenderThreadMsg::Batch(msgs, done) => {
+                for msg in msgs {
+                    break_loop |= self.handle_msg(msg);
                 }
-            };
-
-            break_loop
+                let _ = done.send(());
+            }
         };
 
+        break_loop
+    }
+
+    /// Handle the pending messages, giving up after half a render quantum's
+    /// worth of time so that a flood of messages does not make us miss the
+    /// deadline of the sink. The rest are handled before the next quantum.
+    ///
+    /// Returns true if the render thread should shut down.
+    pub(crate) fn drain_messages(&mut self, event_queue: &ControlReceiver) -> bool {
+        let budget = self.render_size as f64 / self.sample_rate as f64 / 2.;
+        let deadline = Instant::now() + Duration::new(0, (budget * 1e9) as u32);
+        while let Some(msg) = event_queue.try_recv() {
+            if self.handle_msg(msg) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        false
+    }
+
+    /// Handle all the pending messages, with no regard for time. This is for
+    /// a pull context whose renderer has not started yet, see `PullRenderer`.
+    ///
+    /// Returns true if the context got closed.
+    pub(crate) fn handle_messages(&mut self, event_queue: &ControlReceiver) -> bool {
+        while let Some(msg) = event_queue.try_recv() {
+            if self.handle_msg(msg) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn event_loop(&mut self, event_queue: ControlReceiver) {
         loop {
-            if self.sink.has_enough_data() || self.state == ProcessingState::Suspended {
+            if self.sink.has_enough_data() || self.state != ProcessingState::Running {
                 // If we are not processing audio or
                 // if we have already pushed enough data into the audio sink
                 // we wait for messages coming from the control thread or
                 // the audio sink. The audio sink will notify whenever it
                 // needs more data.
-                if let Ok(msg) = event_queue.recv() {
-                    if handle_msg(self, msg) {
+                if let Some(msg) = event_queue.recv() {
+                    if self.handle_msg(msg) {
                         break;
                     }
                 }
             } else {
                 // If we have not pushed enough data into the audio sink yet,
                 // we process the control message queue
-                if let Ok(msg) = event_queue.try_recv() {
-                    if handle_msg(self, msg) {
-                        break;
-                    }
+                if self.drain_messages(&event_queue) {
+                    break;
                 }
 
-                if self.state == ProcessingState::Suspended {
+                if self.state != ProcessingState::Running || self.reach_suspend() {
                     // Bail out if we just suspended processing.
                     continue;
                 }
@@ -241,11 +683,18 @@ impl<B: AudioBackend + 'static> AudioRenderThread<B> {
                 // render quantum.
                 let data = self.process();
                 if self.sink.push_data(data).is_ok() {
-                    // increment current frame by the render quantum size.
-                    self.current_frame += FRAMES_PER_BLOCK;
-                    self.current_time = self.current_frame / self.sample_rate as f64;
+                    self.advance();
+                    let finished = match self.sink {
+                        Sink::Offline(ref sink) => sink.finished(),
+                        _ => false,
+                    };
+                    if finished {
+                        // an offline context is done once rendered
+                        self.set_state(ProcessingState::Closed);
+                    }
                 } else {
                     eprintln!("Could not push data to audio sink");
+                    self.set_state(ProcessingState::Interrupted);
                 }
             }
         }
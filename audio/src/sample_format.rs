@@ -0,0 +1,253 @@
+//! Conversion between the f32 samples we render and integer PCM.
+//!
+//! Converting to integers is done by a `Quantizer`, which can add TPDF
+//! dither to decorrelate the rounding error from the signal, and shape
+//! that error towards high frequencies where it is less audible. Both
+//! need state carried from one buffer to the next, so a sink or exporter
+//! should keep using the same quantizer for a stream.
+//!
+//! Samples are either interleaved, i.e. frame after frame, or planar,
+//! i.e. channel after channel with every channel of the same length.
+//! Converting integers to f32 works the same for both.
+
+/// An integer sample format
+pub trait IntSample: Copy + Default {
+    /// Number of bits of the format
+    const BITS: u32;
+
+    /// Make a sample from a value within the range of the format
+    fn from_i32(value: i32) -> Self;
+
+    fn to_i32(self) -> i32;
+}
+
+impl IntSample for i16 {
+    const BITS: u32 = 16;
+
+    fn from_i32(value: i32) -> Self {
+        value as i16
+    }
+
+    fn to_i32(self) -> i32 {
+        self as i32
+    }
+}
+
+impl IntSample for i32 {
+    const BITS: u32 = 32;
+
+    fn from_i32(value: i32) -> Self {
+        value
+    }
+
+    fn to_i32(self) -> i32 {
+        self
+    }
+}
+
+/// A 24 bit sample, sign extended in the low bits of an i32
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct I24(pub i32);
+
+impl I24 {
+    /// The packed little endian representation, as found in files
+    pub fn to_le_bytes(self) -> [u8; 3] {
+        [self.0 as u8, (self.0 >> 8) as u8, (self.0 >> 16) as u8]
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 3]) -> Self {
+        let value = (bytes[0] as i32) | (bytes[1] as i32) << 8 | (bytes[2] as i32) << 16;
+        // sign extend from bit 23
+        I24((value << 8) >> 8)
+    }
+}
+
+impl IntSample for I24 {
+    const BITS: u32 = 24;
+
+    fn from_i32(value: i32) -> Self {
+        I24(value)
+    }
+
+    fn to_i32(self) -> i32 {
+        self.0
+    }
+}
+
+/// The value of a full scale sample of the format
+fn full_scale<S: IntSample>() -> f64 {
+    (1u64 << (S::BITS - 1)) as f64
+}
+
+/// Convert integer samples to f32 samples in [-1, 1)
+pub fn to_f32<S: IntSample>(src: &[S], dst: &mut [f32]) {
+    debug_assert!(dst.len() == src.len());
+    let scale = 1. / full_scale::<S>();
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = (s.to_i32() as f64 * scale) as f32;
+    }
+}
+
+/// What a `Quantizer` does about the rounding error
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DitherOptions {
+    /// Add triangular noise of up to one least significant bit
+    pub tpdf: bool,
+    /// Feed the rounding error of each sample back into the next one,
+    /// moving the error towards high frequencies
+    pub noise_shaping: bool,
+}
+
+/// Converts f32 samples to integer samples, clipping them to [-1, 1]
+pub struct Quantizer {
+    options: DitherOptions,
+    /// The rounding error of the previous sample of each channel,
+    /// in least significant bits
+    errors: Vec<f64>,
+    /// State of the xorshift generator for the dither
+    rng: u32,
+}
+
+impl Quantizer {
+    pub fn new(channels: u8, options: DitherOptions) -> Self {
+        assert!(channels > 0, "Quantizing needs at least one channel");
+        Quantizer {
+            options,
+            errors: vec![0.; channels as usize],
+            rng: 0x9E37_79B9,
+        }
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.errors.len() as u8
+    }
+
+    /// Convert interleaved samples
+    pub fn quantize_interleaved<S: IntSample>(&mut self, src: &[f32], dst: &mut [S]) {
+        debug_assert!(dst.len() == src.len());
+        let channels = self.errors.len();
+        for (i, (d, s)) in dst.iter_mut().zip(src).enumerate() {
+            *d = self.quantize(*s, i % channels);
+        }
+    }
+
+    /// Convert planar samples, `src.len() / channels` frames of each channel
+    pub fn quantize_planar<S: IntSample>(&mut self, src: &[f32], dst: &mut [S]) {
+        assert_eq!(dst.len(), src.len(), "Mismatched buffer lengths");
+        assert_eq!(
+            src.len() % self.errors.len(),
+            0,
+            "Planar samples must have as many frames for every channel"
+        );
+        let frames = src.len() / self.errors.len();
+        if frames == 0 {
+            return;
+        }
+        for (chan, (d, s)) in dst.chunks_mut(frames).zip(src.chunks(frames)).enumerate() {
+            for (d, s) in d.iter_mut().zip(s) {
+                *d = self.quantize(*s, chan);
+            }
+        }
+    }
+
+    fn quantize<S: IntSample>(&mut self, sample: f32, chan: usize) -> S {
+        let scale = full_scale::<S>();
+        let max = scale - 1.;
+        let mut value = (sample as f64).max(-1.).min(1.) * scale;
+        if self.options.noise_shaping {
+            value -= self.errors[chan];
+        }
+        let mut dithered = value;
+        if self.options.tpdf {
+            dithered += self.uniform() + self.uniform();
+        }
+        let quantized = dithered.round().max(-scale).min(max);
+        if self.options.noise_shaping {
+            // a clipped sample would otherwise feed all of its excess back
+            self.errors[chan] = (quantized - value).max(-2.).min(2.);
+        }
+        S::from_i32(quantized as i32)
+    }
+
+    /// A random value in [-0.5, 0.5)
+    fn uniform(&mut self) -> f64 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x as f64 / 4_294_967_296. - 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn i16_round_trip() {
+        let src: Vec<i16> = (-32768..32768).step_by(7).map(|v| v as i16).collect();
+        let mut floats = vec![0.; src.len()];
+        to_f32(&src, &mut floats);
+        let mut dst = vec![0i16; src.len()];
+        Quantizer::new(1, Default::default()).quantize_interleaved(&floats, &mut dst);
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn i24_round_trip() {
+        let src: Vec<I24> = (-(1 << 23)..(1 << 23)).step_by(4099).map(I24).collect();
+        let mut floats = vec![0.; src.len()];
+        to_f32(&src, &mut floats);
+        let mut dst = vec![I24::default(); src.len()];
+        Quantizer::new(1, Default::default()).quantize_interleaved(&floats, &mut dst);
+        assert_eq!(src, dst);
+        for sample in src {
+            assert_eq!(I24::from_le_bytes(sample.to_le_bytes()), sample);
+        }
+    }
+
+    #[test]
+    fn clips_to_full_scale() {
+        let mut dst = [0i16; 4];
+        Quantizer::new(2, Default::default()).quantize_planar(&[2., 1., -1., -2.], &mut dst);
+        assert_eq!(dst, [32767, 32767, -32768, -32768]);
+    }
+
+    #[test]
+    fn dither_stays_within_one_lsb() {
+        let options = DitherOptions {
+            tpdf: true,
+            noise_shaping: false,
+        };
+        let mut quantizer = Quantizer::new(1, options);
+        // halfway between two values, where the dither decides
+        let src = vec![8192.5 / 32768.; 4096];
+        let mut dst = vec![0i16; src.len()];
+        quantizer.quantize_interleaved(&src, &mut dst);
+        assert!(dst.iter().all(|&v| v == 8192 || v == 8193));
+        let mean = dst.iter().map(|&v| v as f64).sum::<f64>() / dst.len() as f64;
+        assert!((mean - 8192.5).abs() < 0.05, "dither is biased: {}", mean);
+    }
+
+    #[test]
+    fn noise_shaping_keeps_the_average() {
+        let options = DitherOptions {
+            tpdf: true,
+            noise_shaping: true,
+        };
+        let mut quantizer = Quantizer::new(2, options);
+        let src = vec![100.25 / 32768.; 2 * 4096];
+        let mut dst = vec![0i16; src.len()];
+        quantizer.quantize_planar(&src, &mut dst);
+        let mean = dst.iter().map(|&v| v as f64).sum::<f64>() / dst.len() as f64;
+        assert!((mean - 100.25).abs() < 0.01, "noise shaping is biased: {}", mean);
+    }
+
+    #[test]
+    #[should_panic]
+    fn planar_needs_whole_frames() {
+        let mut dst = [0i16; 3];
+        Quantizer::new(2, Default::default()).quantize_planar(&[0.; 3], &mut dst);
+    }
+}
@@ -3,6 +3,9 @@ use render_thread::AudioRenderThreadMsg;
 use std::sync::mpsc::Sender;
 
 pub trait AudioSink {
+    /// Get ready to play at `sample_rate`. The sink sends `SinkNeedData`
+    /// through `render_thread_channel` when it wants more audio, and
+    /// `SinkUnderrun` whenever it ran out of audio to play.
     fn init(
         &self,
         sample_rate: f32,
@@ -11,7 +14,19 @@ pub trait AudioSink {
     fn play(&self) -> Result<(), ()>;
     fn stop(&self) -> Result<(), ()>;
     fn has_enough_data(&self) -> bool;
+    /// Play a render quantum. The chunk holds a single block,
+    /// whose size is that of the render quantum of the context.
     fn push_data(&self, chunk: Chunk) -> Result<(), ()>;
+    /// Seconds between pushing audio and it being heard,
+    /// or 0 if the sink does not know
+    fn latency(&self) -> f64 {
+        0.
+    }
+    /// Seconds of the pushed audio that have been heard so far,
+    /// or `None` if the sink does not know
+    fn position(&self) -> Option<f64> {
+        None
+    }
     fn set_eos_callback(&self, callback: Box<Fn(Box<AsRef<[f32]>>) + Send + Sync + 'static>);
 }
 
@@ -0,0 +1,265 @@
+//! Snapshots of the audio graph, for inspecting a running context.
+//!
+//! Snapshots can be rendered as Graphviz DOT for attaching to bug reports,
+//! or as JSON for tooling.
+
+use graph::{InputPort, NodeId, OutputPort, PortId, PortIndex};
+use node::{AudioNodeType, ChannelCountMode, ChannelInfo, ChannelInterpretation};
+use param::ParamType;
+use std::fmt::Write;
+
+/// Version of the schema of `GraphSnapshot::to_json`, bumped
+/// whenever the output changes in an incompatible way
+pub const JSON_VERSION: u32 = 1;
+
+/// The state of a node at the time of the snapshot
+#[derive(Clone, Debug)]
+pub struct NodeSnapshot {
+    pub id: NodeId,
+    pub node_type: AudioNodeType,
+    pub channel_info: ChannelInfo,
+    pub input_count: u32,
+    pub output_count: u32,
+    /// The current value of each of the params of the node
+    pub params: Vec<(ParamType, f32)>,
+}
+
+/// A connection from an output port to an input port or a param
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionSnapshot {
+    pub output: PortId<OutputPort>,
+    pub input: PortId<InputPort>,
+}
+
+/// All the nodes of an audio graph, and the connections between them
+#[derive(Clone, Debug)]
+pub struct GraphSnapshot {
+    /// The id of the destination node
+    pub destination: NodeId,
+    pub nodes: Vec<NodeSnapshot>,
+    pub connections: Vec<ConnectionSnapshot>,
+}
+
+impl GraphSnapshot {
+    /// Find a node by its id
+    pub fn node(&self, id: NodeId) -> Option<&NodeSnapshot> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    /// Render the graph in the Graphviz DOT language.
+    ///
+    /// Nodes are drawn with their input ports and params at the top and
+    /// their output ports at the bottom. Connections to params are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph AudioGraph {\n");
+        dot.push_str("    node [shape=record];\n");
+        for node in &self.nodes {
+            let mut inputs: Vec<String> = (0..node.input_count)
+                .map(|i| format!("<i{0}> in {0}", i))
+                .collect();
+            inputs.extend(
+                node.params
+                    .iter()
+                    .map(|&(param, value)| format!("<p{0:?}> {0:?} = {1}", param, value)),
+            );
+            let outputs: Vec<String> = (0..node.output_count)
+                .map(|i| format!("<o{0}> out {0}", i))
+                .collect();
+
+            let mut rows = Vec::new();
+            if !inputs.is_empty() {
+                rows.push(format!("{{{}}}", inputs.join("|")));
+            }
+            rows.push(format!(
+                "{} #{}\\n{} channels, {}, {}",
+                node_type_name(&node.node_type),
+                node.id.index(),
+                node.channel_info.count,
+                count_mode_name(node.channel_info.mode),
+                interpretation_name(node.channel_info.interpretation),
+            ));
+            if !outputs.is_empty() {
+                rows.push(format!("{{{}}}", outputs.join("|")));
+            }
+            let _ = write!(
+                dot,
+                "    n{} [label=\"{{{}}}\"{}];\n",
+                node.id.index(),
+                rows.join("|"),
+                if node.id == self.destination {
+                    ", style=bold"
+                } else {
+                    ""
+                },
+            );
+        }
+        for conn in &self.connections {
+            let output = match conn.output.index() {
+                PortIndex::Port(port) => port,
+                PortIndex::Param(never) => never,
+            };
+            let _ = match conn.input.index() {
+                PortIndex::Port(port) => writeln!(
+                    dot,
+                    "    n{}:o{} -> n{}:i{};",
+                    conn.output.node().index(),
+                    output,
+                    conn.input.node().index(),
+                    port
+                ),
+                PortIndex::Param(param) => writeln!(
+                    dot,
+                    "    n{}:o{} -> n{}:p{:?} [style=dashed];",
+                    conn.output.node().index(),
+                    output,
+                    conn.input.node().index(),
+                    param
+                ),
+            };
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Describe the graph as JSON, in the following schema:
+    ///
+    /// ```json
+    /// {
+    ///   "version": 1,
+    ///   "destination": 0,
+    ///   "nodes": [{
+    ///     "id": 3,
+    ///     "type": "GainNode",
+    ///     "channelCount": 2,
+    ///     "channelCountMode": "max",
+    ///     "channelInterpretation": "speakers",
+    ///     "inputs": 1,
+    ///     "outputs": 1,
+    ///     "params": { "gain": 0.5 }
+    ///   }],
+    ///   "connections": [
+    ///     { "from": { "node": 1, "output": 0 }, "to": { "node": 3, "input": 0 } },
+    ///     { "from": { "node": 2, "output": 0 }, "to": { "node": 3, "param": "gain" } }
+    ///   ]
+    /// }
+    /// ```
+    ///
+    /// Enumerations use the names WebAudio gives them. The version is
+    /// `JSON_VERSION`, fields may be added without changing it.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\"version\":{},\"destination\":{},\"nodes\":[",
+            JSON_VERSION,
+            self.destination.index()
+        );
+        for (i, node) in self.nodes.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"id\":{},\"type\":\"{}\",\"channelCount\":{},\
+                 \"channelCountMode\":\"{}\",\"channelInterpretation\":\"{}\",\
+                 \"inputs\":{},\"outputs\":{},\"params\":{{",
+                node.id.index(),
+                node_type_name(&node.node_type),
+                node.channel_info.count,
+                count_mode_name(node.channel_info.mode),
+                interpretation_name(node.channel_info.interpretation),
+                node.input_count,
+                node.output_count,
+            );
+            for (j, &(param, value)) in node.params.iter().enumerate() {
+                if j > 0 {
+                    json.push(',');
+                }
+                let _ = write!(json, "\"{}\":", param_name(param));
+                if value.is_finite() {
+                    let _ = write!(json, "{}", value);
+                } else {
+                    // not representable in JSON
+                    json.push_str("null");
+                }
+            }
+            json.push_str("}}");
+        }
+        json.push_str("],\"connections\":[");
+        for (i, conn) in self.connections.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let output = match conn.output.index() {
+                PortIndex::Port(port) => port,
+                PortIndex::Param(never) => never,
+            };
+            let _ = write!(
+                json,
+                "{{\"from\":{{\"node\":{},\"output\":{}}},\"to\":{{\"node\":{},",
+                conn.output.node().index(),
+                output,
+                conn.input.node().index(),
+            );
+            let _ = match conn.input.index() {
+                PortIndex::Port(port) => write!(json, "\"input\":{}}}}}", port),
+                PortIndex::Param(param) => write!(json, "\"param\":\"{}\"}}}}", param_name(param)),
+            };
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// https://webaudio.github.io/web-audio-api/#enumdef-channelcountmode
+fn count_mode_name(mode: ChannelCountMode) -> &'static str {
+    match mode {
+        ChannelCountMode::Max => "max",
+        ChannelCountMode::ClampedMax => "clamped-max",
+        ChannelCountMode::Explicit => "explicit",
+    }
+}
+
+/// https://webaudio.github.io/web-audio-api/#enumdef-channelinterpretation
+fn interpretation_name(interpretation: ChannelInterpretation) -> &'static str {
+    match interpretation {
+        ChannelInterpretation::Discrete => "discrete",
+        ChannelInterpretation::Speakers => "speakers",
+    }
+}
+
+/// The name of the attribute of the param on its node
+fn param_name(param: ParamType) -> &'static str {
+    match param {
+        ParamType::Frequency => "frequency",
+        ParamType::Detune => "detune",
+        ParamType::Gain => "gain",
+        ParamType::PlaybackRate => "playbackRate",
+    }
+}
+
+/// The name of the type of a node, part of the schema of `to_json`
+fn node_type_name(node_type: &AudioNodeType) -> &'static str {
+    match *node_type {
+        AudioNodeType::AnalyserNode => "AnalyserNode",
+        AudioNodeType::BiquadFilterNode => "BiquadFilterNode",
+        AudioNodeType::AudioBuffer => "AudioBuffer",
+        AudioNodeType::AudioBufferSourceNode => "AudioBufferSourceNode",
+        AudioNodeType::ChannelMergerNode => "ChannelMergerNode",
+        AudioNodeType::ChannelSplitterNode => "ChannelSplitterNode",
+        AudioNodeType::ConstantSourceNode => "ConstantSourceNode",
+        AudioNodeType::ConvolverNode => "ConvolverNode",
+        AudioNodeType::DelayNode => "DelayNode",
+        AudioNodeType::DestinationNode => "DestinationNode",
+        AudioNodeType::DynamicsCompressionNode => "DynamicsCompressionNode",
+        AudioNodeType::GainNode => "GainNode",
+        AudioNodeType::IIRFilterNode => "IIRFilterNode",
+        AudioNodeType::OscillatorNode => "OscillatorNode",
+        AudioNodeType::PannerNode => "PannerNode",
+        AudioNodeType::PeriodicWave => "PeriodicWave",
+        AudioNodeType::ScriptProcessorNode => "ScriptProcessorNode",
+        AudioNodeType::StereoPannerNode => "StereoPannerNode",
+        AudioNodeType::WaveShaperNode => "WaveShaperNode",
+    }
+}
@@ -0,0 +1,293 @@
+//! Threads helping the render thread process the audio graph.
+//!
+//! The graph schedule is split into levels of nodes that do not depend on
+//! each other. For each level, the render thread hands the pool a job, and
+//! the workers and the render thread itself claim nodes until there are
+//! none left. The render thread only moves on to the next level once every
+//! worker is done with the current one.
+//!
+//! Waiting for and handing out jobs only uses a mutex, a couple of condition
+//! variables and an atomic counter, none of which allocate. Each worker
+//! has its own scratch space, which the render thread reserves along with
+//! its own whenever the schedule changes.
+
+use graph::Scratch;
+use pool::{self, SharedPool};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, Builder, JoinHandle};
+
+type Work = Fn(usize, &mut Scratch) + Sync;
+
+/// A level being processed
+#[derive(Clone, Copy)]
+struct Job {
+    /// The work to do for each node of the level.
+    ///
+    /// This borrows from the stack of the render thread, which is
+    /// fine since it waits for all the workers before leaving
+    /// `WorkerPool::run`, even when unwinding.
+    work: *const Work,
+    len: usize,
+    /// Whether the render thread counts its allocations,
+    /// which then include those of the workers
+    #[cfg(feature = "alloc_check")]
+    counting: bool,
+    /// Whether the pool may grow, see `alloc_check::warm_up`
+    #[cfg(feature = "alloc_check")]
+    warming_up: bool,
+}
+
+// The work is Sync, and only used while the render thread waits for it
+unsafe impl Send for Job {}
+
+impl Job {
+    fn run(&self, next: &AtomicUsize, scratch: &mut Scratch) {
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            if i >= self.len {
+                break;
+            }
+            unsafe { (*self.work)(i, scratch) }
+        }
+    }
+
+    /// Run the job on a worker, adding the allocations it makes
+    /// to `allocations` if the render thread counts its own
+    #[cfg(feature = "alloc_check")]
+    fn run_counted(&self, next: &AtomicUsize, scratch: &mut Scratch, allocations: &AtomicUsize) {
+        use alloc_check;
+
+        let mut run = || {
+            if self.warming_up {
+                alloc_check::warm_up(|| self.run(next, scratch))
+            } else {
+                self.run(next, scratch)
+            }
+        };
+        if self.counting {
+            let ((), count) = alloc_check::count_allocations(run);
+            allocations.fetch_add(count, Ordering::Relaxed);
+        } else {
+            run()
+        }
+    }
+}
+
+struct State {
+    job: Option<Job>,
+    /// Incremented for every new job, so that workers can tell
+    /// it apart from the one they just finished
+    generation: usize,
+    /// Number of workers that have not finished the current job
+    busy: usize,
+    /// Number of workers still around to take jobs
+    alive: usize,
+    /// Whether a worker panicked while processing a node
+    panicked: bool,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when there is a new job, or on shutdown
+    work: Condvar,
+    /// Signalled when the last worker finishes the current job
+    done: Condvar,
+    /// Index of the next node to be claimed in the current job
+    next: AtomicUsize,
+    /// Allocations made by the workers during the current job
+    #[cfg(feature = "alloc_check")]
+    allocations: AtomicUsize,
+}
+
+pub(crate) struct WorkerPool {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+    /// The scratch space of each worker, only ever
+    /// locked by another thread between jobs
+    scratch: Vec<Arc<Mutex<Scratch>>>,
+    buffers: Arc<SharedPool>,
+}
+
+impl WorkerPool {
+    /// Spawn `workers` threads, which exchange block buffers through
+    /// `buffers`. The render thread needs to `join` them to do so too.
+    pub fn new(workers: usize, buffers: Arc<SharedPool>) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                job: None,
+                generation: 0,
+                busy: 0,
+                alive: workers,
+                panicked: false,
+                shutdown: false,
+            }),
+            work: Condvar::new(),
+            done: Condvar::new(),
+            next: AtomicUsize::new(0),
+            #[cfg(feature = "alloc_check")]
+            allocations: AtomicUsize::new(0),
+        });
+        let scratch: Vec<_> = (0..workers)
+            .map(|_| Arc::new(Mutex::new(Scratch::default())))
+            .collect();
+        let threads = scratch
+            .iter()
+            .enumerate()
+            .map(|(i, scratch)| {
+                let shared = shared.clone();
+                let scratch = scratch.clone();
+                let buffers = buffers.clone();
+                Builder::new()
+                    .name(format!("AudioWorker{}", i))
+                    .spawn(move || {
+                        pool::share(Some(buffers));
+                        worker(&shared, &scratch);
+                    })
+                    .expect("Could not spawn audio worker")
+            })
+            .collect();
+        WorkerPool {
+            shared,
+            threads,
+            scratch,
+            buffers,
+        }
+    }
+
+    /// Grow the scratch space of every worker with `reserve`,
+    /// so that they do not need to allocate while processing
+    pub fn reserve_scratch<F: Fn(&mut Scratch)>(&self, reserve: F) {
+        for scratch in &self.scratch {
+            // a worker that panicked is gone, leave its scratch alone
+            if let Ok(mut scratch) = scratch.lock() {
+                reserve(&mut scratch);
+            }
+        }
+    }
+
+    /// Make the calling thread exchange block buffers with the workers
+    pub fn join(&self) {
+        pool::share(Some(self.buffers.clone()));
+    }
+
+    /// Call `work` for every index in `0..len`, spreading the calls over
+    /// the workers and the calling thread. Returns once all calls are done.
+    pub fn run(
+        &mut self,
+        len: usize,
+        scratch: &mut Scratch,
+        work: &(Fn(usize, &mut Scratch) + Sync),
+    ) {
+        let job = Job {
+            // erase the lifetime, see `Job::work`
+            work: unsafe { mem::transmute(work) },
+            len,
+            #[cfg(feature = "alloc_check")]
+            counting: ::alloc_check::is_counting(),
+            #[cfg(feature = "alloc_check")]
+            warming_up: ::alloc_check::is_warming_up(),
+        };
+        self.shared.next.store(0, Ordering::Relaxed);
+        #[cfg(feature = "alloc_check")]
+        self.shared.allocations.store(0, Ordering::Relaxed);
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.job = Some(job);
+            state.generation += 1;
+            state.busy = state.alive;
+        }
+        self.shared.work.notify_all();
+
+        {
+            let _wait = WaitForWorkers(&self.shared);
+            job.run(&self.shared.next, scratch);
+        }
+        #[cfg(feature = "alloc_check")]
+        ::alloc_check::record(self.shared.allocations.load(Ordering::Relaxed));
+
+        // Some nodes of the level may not have been processed,
+        // so carry on as if the render thread had panicked itself
+        if self.shared.state.lock().unwrap().panicked {
+            panic!("An audio worker panicked");
+        }
+    }
+}
+
+/// Waits for the workers to finish the current job when dropped, so that
+/// the work is not freed under them if the render thread unwinds
+struct WaitForWorkers<'a>(&'a Shared);
+
+impl<'a> Drop for WaitForWorkers<'a> {
+    fn drop(&mut self) {
+        let mut state = match self.0.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        while state.busy > 0 {
+            state = match self.0.done.wait(state) {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+        }
+        state.job = None;
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.work.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        pool::share(None);
+    }
+}
+
+/// Marks the worker as done with the current job when dropped. If processing
+/// a node panicked, the worker is also no longer counted for later jobs,
+/// since its thread is about to exit.
+struct Finished<'a>(&'a Shared);
+
+impl<'a> Drop for Finished<'a> {
+    fn drop(&mut self) {
+        let mut state = match self.0.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if thread::panicking() {
+            state.alive -= 1;
+            state.panicked = true;
+        }
+        state.busy -= 1;
+        if state.busy == 0 {
+            self.0.done.notify_one();
+        }
+    }
+}
+
+fn worker(shared: &Shared, scratch: &Mutex<Scratch>) {
+    let mut generation = 0;
+    loop {
+        let job = {
+            let mut state = shared.state.lock().unwrap();
+            while !state.shutdown && state.generation == generation {
+                state = shared.work.wait(state).unwrap();
+            }
+            if state.shutdown {
+                return;
+            }
+            generation = state.generation;
+            state.job.expect("A new job should have been posted")
+        };
+        let _finished = Finished(shared);
+        let mut scratch = scratch.lock().unwrap();
+        #[cfg(feature = "alloc_check")]
+        job.run_counted(&shared.next, &mut scratch, &shared.allocations);
+        #[cfg(not(feature = "alloc_check"))]
+        job.run(&shared.next, &mut scratch);
+    }
+}
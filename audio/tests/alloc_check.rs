@@ -0,0 +1,90 @@
+//! Run with `cargo test --features alloc_check`.
+#![cfg(feature = "alloc_check")]
+
+extern crate servo_media_audio;
+
+use servo_media_audio::alloc_check::{count_allocations, warm_up};
+use servo_media_audio::block::{Tick, FRAMES_PER_BLOCK_USIZE};
+use servo_media_audio::channel_node::ChannelNodeOptions;
+use servo_media_audio::node::{AudioNodeInit, AudioNodeMessage, BlockInfo};
+use servo_media_audio::node::AudioScheduledSourceNodeMessage;
+use servo_media_audio::offline_renderer::{OfflineRenderer, OfflineRendererOptions};
+use servo_media_audio::pool;
+
+const WARM_UP_QUANTA: u64 = 4;
+const CHECKED_QUANTA: u64 = 256;
+
+#[test]
+fn steady_state_rendering_does_not_allocate() {
+    check_steady_state(1);
+}
+
+#[test]
+fn steady_state_rendering_on_workers_does_not_allocate() {
+    check_steady_state(3);
+}
+
+#[test]
+fn pool_misses_are_counted_after_warm_up() {
+    let (buffer, allocations) = count_allocations(|| warm_up(|| pool::take_buffer(2, 128)));
+    assert_eq!(allocations, 0);
+    let (_, allocations) = count_allocations(|| pool::take_buffer(2, 128));
+    assert_eq!(allocations, 1);
+    pool::recycle_buffer(buffer, 128);
+    let (_, allocations) = count_allocations(|| pool::take_buffer(2, 128));
+    assert_eq!(allocations, 0);
+}
+
+fn check_steady_state(render_threads: usize) {
+    let mut renderer = OfflineRenderer::new(OfflineRendererOptions {
+        render_threads,
+        ..Default::default()
+    }).unwrap();
+    let dest = renderer.dest_node();
+    let merger = renderer
+        .create_node(AudioNodeInit::ChannelMergerNode(ChannelNodeOptions { channels: 2 }))
+        .unwrap();
+    renderer.connect_ports(merger.output(0), dest.input(0)).unwrap();
+    // independent sources, so that several threads have work to do
+    for port in 0..2 {
+        let osc = renderer
+            .create_node(AudioNodeInit::OscillatorNode(Default::default()))
+            .unwrap();
+        let gain = renderer
+            .create_node(AudioNodeInit::GainNode(Default::default()))
+            .unwrap();
+        renderer.connect_ports(osc.output(0), gain.input(0)).unwrap();
+        renderer.connect_ports(gain.output(0), merger.input(port)).unwrap();
+        renderer
+            .message_node(
+                osc,
+                AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
+            )
+            .unwrap();
+    }
+
+    let sample_rate = renderer.sample_rate();
+    let graph = renderer.graph_mut();
+    let mut render = |quantum: u64| {
+        let frame = Tick(quantum * FRAMES_PER_BLOCK_USIZE as u64);
+        let info = BlockInfo {
+            sample_rate,
+            frame,
+            time: frame / sample_rate as f64,
+            frames: FRAMES_PER_BLOCK_USIZE,
+        };
+        // dropping the chunk gives its buffers back to the pool
+        graph.process(&info);
+    };
+
+    // let the schedule and the block pool grow to the working set
+    for quantum in 0..WARM_UP_QUANTA {
+        render(quantum);
+    }
+    let ((), allocations) = count_allocations(|| {
+        for quantum in WARM_UP_QUANTA..WARM_UP_QUANTA + CHECKED_QUANTA {
+            render(quantum);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
@@ -0,0 +1,48 @@
+extern crate servo_media_audio;
+
+use servo_media_audio::context::AudioContext;
+use servo_media_audio::decoder::DummyAudioDecoder;
+use servo_media_audio::error::AudioError;
+use servo_media_audio::node::{AudioNodeInit, AudioNodeMessage};
+use servo_media_audio::param::{ParamType, UserAutomationEvent};
+use servo_media_audio::sink::DummyAudioSink;
+use servo_media_audio::AudioBackend;
+
+struct DummyBackend;
+
+impl AudioBackend for DummyBackend {
+    type Decoder = DummyAudioDecoder;
+    type Sink = DummyAudioSink;
+    fn make_decoder() -> Self::Decoder {
+        DummyAudioDecoder
+    }
+    fn make_sink() -> Result<Self::Sink, ()> {
+        Ok(DummyAudioSink)
+    }
+    fn init() {}
+}
+
+#[test]
+fn node_messages_do_not_wait_for_the_renderer() {
+    let (context, mut renderer) =
+        AudioContext::<DummyBackend>::new_pull(Default::default()).unwrap();
+    let gain = context
+        .create_node(AudioNodeInit::GainNode(Default::default()))
+        .unwrap();
+    context.resume().unwrap();
+    renderer.prepare();
+
+    // nothing handles these until the host asks for audio
+    for i in 0..1000 {
+        let event = UserAutomationEvent::SetValueAtTime(i as f32, i as f64);
+        context.message_node(gain, AudioNodeMessage::SetParam(ParamType::Gain, event));
+    }
+    let event = UserAutomationEvent::SetValue(440.);
+    context.message_node(gain, AudioNodeMessage::SetParam(ParamType::Frequency, event));
+    assert_eq!(context.check_messages(), Ok(()));
+
+    let mut out = [0.; 256];
+    renderer.render_into(&mut out, 2);
+    assert_eq!(context.check_messages(), Err(AudioError::InvalidAccess));
+    assert_eq!(context.check_messages(), Ok(()));
+}
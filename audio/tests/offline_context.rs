@@ -0,0 +1,28 @@
+extern crate servo_media_audio;
+
+use servo_media_audio::context::{AudioContext, AudioContextOptions, OfflineAudioContextOptions};
+use servo_media_audio::decoder::DummyAudioDecoder;
+use servo_media_audio::error::AudioError;
+use servo_media_audio::sink::DummyAudioSink;
+use servo_media_audio::AudioBackend;
+
+struct DummyBackend;
+
+impl AudioBackend for DummyBackend {
+    type Decoder = DummyAudioDecoder;
+    type Sink = DummyAudioSink;
+    fn make_decoder() -> Self::Decoder {
+        DummyAudioDecoder
+    }
+    fn make_sink() -> Result<Self::Sink, ()> {
+        Ok(DummyAudioSink)
+    }
+    fn init() {}
+}
+
+#[test]
+fn offline_context_with_no_frames_is_rejected() {
+    let options = AudioContextOptions::OfflineAudioContext(OfflineAudioContextOptions::default());
+    let context = AudioContext::<DummyBackend>::new(options);
+    assert_eq!(context.err(), Some(AudioError::NotSupported));
+}
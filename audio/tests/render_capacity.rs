@@ -0,0 +1,79 @@
+extern crate servo_media_audio;
+
+use servo_media_audio::block::Chunk;
+use servo_media_audio::context::{AudioContext, AudioContextOptions};
+use servo_media_audio::decoder::DummyAudioDecoder;
+use servo_media_audio::render_thread::AudioRenderThreadMsg;
+use servo_media_audio::sink::AudioSink;
+use servo_media_audio::AudioBackend;
+use std::cell::RefCell;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A sink that runs out of audio after every other quantum
+struct StarvingSink {
+    channel: RefCell<Option<Sender<AudioRenderThreadMsg>>>,
+    pushed: RefCell<u64>,
+}
+
+impl AudioSink for StarvingSink {
+    fn init(&self, _: f32, channel: Sender<AudioRenderThreadMsg>) -> Result<(), ()> {
+        *self.channel.borrow_mut() = Some(channel);
+        Ok(())
+    }
+    fn play(&self) -> Result<(), ()> {
+        Ok(())
+    }
+    fn stop(&self) -> Result<(), ()> {
+        Ok(())
+    }
+    fn has_enough_data(&self) -> bool {
+        // only take a few quanta, so that the counts are known
+        *self.pushed.borrow() >= 4
+    }
+    fn push_data(&self, _: Chunk) -> Result<(), ()> {
+        *self.pushed.borrow_mut() += 1;
+        if *self.pushed.borrow() % 2 == 0 {
+            let channel = self.channel.borrow();
+            let _ = channel.as_ref().unwrap().send(AudioRenderThreadMsg::SinkUnderrun);
+        }
+        Ok(())
+    }
+    fn set_eos_callback(&self, _: Box<Fn(Box<AsRef<[f32]>>) + Send + Sync + 'static>) {}
+}
+
+struct StarvingBackend;
+
+impl AudioBackend for StarvingBackend {
+    type Decoder = DummyAudioDecoder;
+    type Sink = StarvingSink;
+    fn make_decoder() -> Self::Decoder {
+        DummyAudioDecoder
+    }
+    fn make_sink() -> Result<Self::Sink, ()> {
+        Ok(StarvingSink {
+            channel: RefCell::new(None),
+            pushed: RefCell::new(0),
+        })
+    }
+    fn init() {}
+}
+
+#[test]
+fn sink_underruns_are_counted() {
+    let options = AudioContextOptions::RealTimeAudioContext(Default::default());
+    let context = AudioContext::<StarvingBackend>::new(options).unwrap();
+    context.resume().unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let (mut underruns, mut quanta) = (0, 0);
+    while quanta < 4 || underruns < 2 {
+        assert!(Instant::now() < deadline, "The sink was not fed");
+        thread::sleep(Duration::from_millis(1));
+        let stats = context.render_capacity();
+        underruns += stats.underruns;
+        quanta += stats.quanta;
+    }
+    assert_eq!((underruns, quanta), (2, 4));
+}
@@ -3,7 +3,7 @@ use gst;
 use gst::prelude::*;
 use gst_app::{AppSrc, AppSrcCallbacks};
 use gst_audio;
-use servo_media_audio::block::{Chunk, FRAMES_PER_BLOCK};
+use servo_media_audio::block::Chunk;
 use servo_media_audio::render_thread::AudioRenderThreadMsg;
 use servo_media_audio::sink::AudioSink;
 use std::cell::{Cell, RefCell};
@@ -21,6 +21,8 @@ pub struct GStreamerAudioSink {
     sample_rate: Cell<f32>,
     audio_info: RefCell<Option<gst_audio::AudioInfo>>,
     sample_offset: Cell<u64>,
+    /// Where underruns are reported, once initialized
+    render_thread_channel: RefCell<Option<Sender<AudioRenderThreadMsg>>>,
 }
 
 impl GStreamerAudioSink {
@@ -38,6 +40,7 @@ impl GStreamerAudioSink {
             sample_rate: Cell::new(DEFAULT_SAMPLE_RATE),
             audio_info: RefCell::new(None),
             sample_offset: Cell::new(0),
+            render_thread_channel: RefCell::new(None),
         })
     }
 }
@@ -81,6 +84,8 @@ impl AudioSink for GStreamerAudioSink {
         // Allow only a single chunk.
         self.appsrc.set_max_bytes(1);
 
+        *self.render_thread_channel.borrow_mut() = Some(graph_thread_channel.clone());
+
         let appsrc = self.appsrc.clone();
         Builder::new()
             .name("GstAppSrcCallbacks".to_owned())
@@ -127,9 +132,7 @@ impl AudioSink for GStreamerAudioSink {
     }
 
     fn push_data(&self, mut chunk: Chunk) -> Result<(), ()> {
-        if let Some(block) = chunk.blocks.get(0) {
-            self.set_channels_if_changed(block.chan_count())?;
-        }
+        self.set_channels_if_changed(chunk.blocks[0].chan_count())?;
 
         let sample_rate = self.sample_rate.get() as u64;
         let audio_info = self.audio_info.borrow();
@@ -137,7 +140,7 @@ impl AudioSink for GStreamerAudioSink {
         let channels = audio_info.channels();
         let bpf = audio_info.bpf() as usize;
         assert!(bpf == 4 * channels as usize);
-        let n_samples = FRAMES_PER_BLOCK.0 as u64;
+        let n_samples = chunk.blocks[0].frames() as u64;
         let buf_size = (n_samples as usize) * (bpf);
         let mut buffer = gst::Buffer::with_size(buf_size).unwrap();
         {
@@ -157,19 +160,27 @@ impl AudioSink for GStreamerAudioSink {
             buffer.set_pts(pts);
             buffer.set_duration(next_pts - pts);
 
-            // sometimes nothing reaches the output
-            if chunk.len() == 0 {
-                chunk.blocks.push(Default::default());
-                chunk.blocks[0].repeat(channels as u8);
-            }
             debug_assert!(chunk.len() == 1);
-            let mut data = chunk.blocks[0].interleave();
-            let data = data.as_mut_byte_slice().expect("casting failed");
+            {
+                // Interleave straight into the buffer's memory rather than
+                // going through an intermediate allocation
+                let mut map = buffer.map_writable().expect("mapping failed");
+                let data = map
+                    .as_mut_slice()
+                    .as_mut_slice_of::<f32>()
+                    .expect("casting failed");
+                chunk.blocks[0].interleave_into(data);
+            }
 
-            // XXXManishearth if we have a safe way to convert
-            // from Box<[f32]> to Box<[u8]> (similarly for Vec)
-            // we can use Buffer::from_slice instead
-            buffer.copy_from_slice(0, data).expect("copying failed");
+            // appsrc asks for data whenever its single chunk has been
+            // taken, so that does not tell whether the pipeline starved.
+            // It did if it already played past where this buffer goes.
+            let played = self.pipeline.query_position::<gst::ClockTime>();
+            if played.map_or(false, |played| played > pts) {
+                if let Some(ref channel) = *self.render_thread_channel.borrow() {
+                    let _ = channel.send(AudioRenderThreadMsg::SinkUnderrun);
+                }
+            }
 
             sample_offset += n_samples;
             self.sample_offset.set(sample_offset);
@@ -182,6 +193,27 @@ impl AudioSink for GStreamerAudioSink {
             .map_err(|_| ())
     }
 
+    fn latency(&self) -> f64 {
+        let mut query = gst::Query::new_latency();
+        if !self.pipeline.query(query.get_mut().unwrap()) {
+            return 0.;
+        }
+        match query.view() {
+            gst::QueryView::Latency(ref latency) => {
+                let (_, min, _) = latency.get_result();
+                min.nseconds().unwrap_or(0) as f64 / gst::SECOND_VAL as f64
+            }
+            _ => 0.,
+        }
+    }
+
+    fn position(&self) -> Option<f64> {
+        // The buffers are timestamped from the start of the context, and
+        // the position accounts for what is still queued in appsrc
+        let position = self.pipeline.query_position::<gst::ClockTime>()?;
+        Some(position.nseconds()? as f64 / gst::SECOND_VAL as f64)
+    }
+
     fn set_eos_callback(&self, _: Box<Fn(Box<AsRef<[f32]>>) + Send + Sync + 'static>) {}
 }
 
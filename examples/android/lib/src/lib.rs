@@ -12,14 +12,17 @@ struct AudioStream {
 
 impl AudioStream {
     pub fn new() -> Self {
-        let context = ServoMedia::get().unwrap().create_audio_context(Default::default());
-        let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default()));
+        let context = ServoMedia::get()
+            .unwrap()
+            .create_audio_context(Default::default())
+            .unwrap();
+        let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default())).unwrap();
         let mut options = GainNodeOptions::default();
         options.gain = 0.5;
-        let gain = context.create_node(AudioNodeInit::GainNode(options));
+        let gain = context.create_node(AudioNodeInit::GainNode(options)).unwrap();
         let dest = context.dest_node();
-        context.connect_ports(osc.output(0), gain.input(0));
-        context.connect_ports(gain.output(0), dest.input(0));
+        context.connect_ports(osc.output(0), gain.input(0)).unwrap();
+        context.connect_ports(gain.output(0), dest.input(0)).unwrap();
         context.message_node(
             osc,
             AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
@@ -12,7 +12,7 @@ use std::sync::{Arc, Mutex};
 use std::{thread, time};
 
 fn run_example(servo_media: Arc<ServoMedia>) {
-    let context = servo_media.create_audio_context(Default::default());
+    let context = servo_media.create_audio_context(Default::default()).unwrap();
     let args: Vec<_> = env::args().collect();
     let filename: &str = if args.len() == 2 {
         args[1].as_ref()
@@ -44,9 +44,9 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     receiver.recv().unwrap();
     println!("Audio decoded");
     let buffer_source =
-        context.create_node(AudioNodeInit::AudioBufferSourceNode(Default::default()));
+        context.create_node(AudioNodeInit::AudioBufferSourceNode(Default::default())).unwrap();
     let dest = context.dest_node();
-    context.connect_ports(buffer_source.output(0), dest.input(0));
+    context.connect_ports(buffer_source.output(0), dest.input(0)).unwrap();
     context.message_node(
         buffer_source,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
@@ -8,22 +8,22 @@ use std::sync::Arc;
 use std::{thread, time};
 
 fn run_example(servo_media: Arc<ServoMedia>) {
-    let context = servo_media.create_audio_context(Default::default());
+    let context = servo_media.create_audio_context(Default::default()).unwrap();
     let mut options = Default::default();
-    let osc = context.create_node(AudioNodeInit::OscillatorNode(options));
+    let osc = context.create_node(AudioNodeInit::OscillatorNode(options)).unwrap();
     options.freq = 213.;
-    let osc2 = context.create_node(AudioNodeInit::OscillatorNode(options));
+    let osc2 = context.create_node(AudioNodeInit::OscillatorNode(options)).unwrap();
     let mut options = GainNodeOptions::default();
     options.gain = 0.7;
-    let gain = context.create_node(AudioNodeInit::GainNode(options));
+    let gain = context.create_node(AudioNodeInit::GainNode(options)).unwrap();
     let options = ChannelNodeOptions { channels: 2 };
-    let merger = context.create_node(AudioNodeInit::ChannelMergerNode(options));
+    let merger = context.create_node(AudioNodeInit::ChannelMergerNode(options)).unwrap();
 
     let dest = context.dest_node();
-    context.connect_ports(osc.output(0), gain.input(0));
-    context.connect_ports(gain.output(0), merger.input(0));
-    context.connect_ports(osc2.output(0), merger.input(1));
-    context.connect_ports(merger.output(0), dest.input(0));
+    context.connect_ports(osc.output(0), gain.input(0)).unwrap();
+    context.connect_ports(gain.output(0), merger.input(0)).unwrap();
+    context.connect_ports(osc2.output(0), merger.input(1)).unwrap();
+    context.connect_ports(merger.output(0), dest.input(0)).unwrap();
     context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
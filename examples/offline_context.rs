@@ -16,7 +16,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     options.channels = 2;
     options.length = 1024 * FRAMES_PER_BLOCK_USIZE;
     let options = AudioContextOptions::OfflineAudioContext(options);
-    let context = servo_media.create_audio_context(options);
+    let context = servo_media.create_audio_context(options).unwrap();
     let processed_audio = Arc::new(Mutex::new(Vec::new()));
     let processed_audio_ = processed_audio.clone();
     let (sender, receiver) = mpsc::channel();
@@ -25,9 +25,9 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         processed_audio.lock().unwrap().extend_from_slice((*buffer).as_ref());
         sender.lock().unwrap().send(()).unwrap();
     }));
-    let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default()));
+    let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default())).unwrap();
     let dest = context.dest_node();
-    context.connect_ports(osc.output(0), dest.input(0));
+    context.connect_ports(osc.output(0), dest.input(0)).unwrap();
     context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
@@ -38,11 +38,11 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     // Close offline context.
     let _ = context.close();
     // Create audio context to play the processed audio.
-    let context = servo_media.create_audio_context(Default::default());
+    let context = servo_media.create_audio_context(Default::default()).unwrap();
     let buffer_source =
-        context.create_node(AudioNodeInit::AudioBufferSourceNode(Default::default()));
+        context.create_node(AudioNodeInit::AudioBufferSourceNode(Default::default())).unwrap();
     let dest = context.dest_node();
-    context.connect_ports(buffer_source.output(0), dest.input(0));
+    context.connect_ports(buffer_source.output(0), dest.input(0)).unwrap();
     context.message_node(
         buffer_source,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
@@ -10,16 +10,16 @@ use std::sync::Arc;
 use std::{thread, time};
 
 fn run_example(servo_media: Arc<ServoMedia>) {
-    let context = servo_media.create_audio_context(Default::default());
+    let context = servo_media.create_audio_context(Default::default()).unwrap();
     let mut options = OscillatorNodeOptions::default();
     options.freq = 2.0;
-    let lfo = context.create_node(AudioNodeInit::OscillatorNode(options));
-    let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default()));
-    let gain = context.create_node(AudioNodeInit::GainNode(Default::default()));
+    let lfo = context.create_node(AudioNodeInit::OscillatorNode(options)).unwrap();
+    let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default())).unwrap();
+    let gain = context.create_node(AudioNodeInit::GainNode(Default::default())).unwrap();
     let dest = context.dest_node();
-    context.connect_ports(lfo.output(0), gain.param(ParamType::Gain));
-    context.connect_ports(gain.output(0), dest.input(0));
-    context.connect_ports(osc.output(0), gain.input(0));
+    context.connect_ports(lfo.output(0), gain.param(ParamType::Gain)).unwrap();
+    context.connect_ports(gain.output(0), dest.input(0)).unwrap();
+    context.connect_ports(osc.output(0), gain.input(0)).unwrap();
     let _ = context.resume();
     context.message_node(
         osc,
@@ -11,18 +11,18 @@ use std::sync::Arc;
 use std::{thread, time};
 
 fn run_example(servo_media: Arc<ServoMedia>) {
-    let context = servo_media.create_audio_context(Default::default());
+    let context = servo_media.create_audio_context(Default::default()).unwrap();
     let mut options = OscillatorNodeOptions::default();
     options.freq = 2.0;
-    let lfo = context.create_node(AudioNodeInit::OscillatorNode(options));
-    let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default()));
+    let lfo = context.create_node(AudioNodeInit::OscillatorNode(options)).unwrap();
+    let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default())).unwrap();
     let mut options = GainNodeOptions::default();
     options.gain = 100.;
-    let gain = context.create_node(AudioNodeInit::GainNode(options));
+    let gain = context.create_node(AudioNodeInit::GainNode(options)).unwrap();
     let dest = context.dest_node();
-    context.connect_ports(lfo.output(0), gain.input(0));
-    context.connect_ports(gain.output(0), osc.param(ParamType::Frequency));
-    context.connect_ports(osc.output(0), dest.input(0));
+    context.connect_ports(lfo.output(0), gain.input(0)).unwrap();
+    context.connect_ports(gain.output(0), osc.param(ParamType::Frequency)).unwrap();
+    context.connect_ports(osc.output(0), dest.input(0)).unwrap();
     let _ = context.resume();
     context.message_node(
         osc,
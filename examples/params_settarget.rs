@@ -7,10 +7,10 @@ use std::sync::Arc;
 use std::{thread, time};
 
 fn run_example(servo_media: Arc<ServoMedia>) {
-    let context = servo_media.create_audio_context(Default::default());
+    let context = servo_media.create_audio_context(Default::default()).unwrap();
     let dest = context.dest_node();
-    let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default()));
-    context.connect_ports(osc.output(0), dest.input(0));
+    let osc = context.create_node(AudioNodeInit::OscillatorNode(Default::default())).unwrap();
+    context.connect_ports(osc.output(0), dest.input(0)).unwrap();
     let _ = context.resume();
     context.message_node(
         osc,
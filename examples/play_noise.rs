@@ -9,11 +9,11 @@ use std::sync::Arc;
 use std::{thread, time};
 
 fn run_example(servo_media: Arc<ServoMedia>) {
-    let context = servo_media.create_audio_context(Default::default());
+    let context = servo_media.create_audio_context(Default::default()).unwrap();
     let buffer_source =
-        context.create_node(AudioNodeInit::AudioBufferSourceNode(Default::default()));
+        context.create_node(AudioNodeInit::AudioBufferSourceNode(Default::default())).unwrap();
     let dest = context.dest_node();
-    context.connect_ports(buffer_source.output(0), dest.input(0));
+    context.connect_ports(buffer_source.output(0), dest.input(0)).unwrap();
     let mut buffers = vec![Vec::with_capacity(4096), Vec::with_capacity(4096)];
     for _ in 0..4096 {
         buffers[0].push(rand::random::<f32>());
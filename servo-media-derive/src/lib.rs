@@ -35,6 +35,22 @@ fn impl_audio_scheduled_source_node(ast: &syn::DeriveInput) -> quote::Tokens {
                 }
             }
 
+            /// Whether the block needs processing: the source starts playing
+            /// before the end of the block and has not stopped yet, or it has
+            /// stopped but the ended event has not been fired yet
+            fn should_process_at(&self, info: &::node::BlockInfo) -> bool {
+                let tick = info.frame;
+                match self.start_at {
+                    Some(start_at) if start_at < tick + info.frames as u64 => {
+                        match self.stop_at {
+                            Some(stop_at) if stop_at <= tick => self.onended_callback.is_some(),
+                            _ => true,
+                        }
+                    }
+                    _ => false,
+                }
+            }
+
             fn start(&mut self, tick: Tick) -> bool {
                 // We can only allow a single call to `start` and always before
                 // any `stop` calls.
@@ -61,7 +77,7 @@ fn impl_audio_scheduled_source_node(ast: &syn::DeriveInput) -> quote::Tokens {
                 if self.start_at.is_none() || self.onended_callback.is_none() {
                     return;
                 }
-                self.onended_callback.take().unwrap().0();
+                self.onended_callback.take().unwrap().call();
             }
 
             fn handle_source_node_message(&mut self, message: AudioScheduledSourceNodeMessage, sample_rate: f32) {
@@ -6,6 +6,7 @@ use std::sync::{self, Arc, Mutex, Once};
 
 use audio::context::{AudioContext, AudioContextOptions};
 use audio::decoder::DummyAudioDecoder;
+use audio::error::AudioResult;
 use audio::sink::DummyAudioSink;
 use audio::AudioBackend;
 use player::{DummyPlayer, Player, PlayerBackend};
@@ -60,7 +61,10 @@ impl ServoMedia {
         }
     }
 
-    pub fn create_audio_context(&self, options: AudioContextOptions) -> AudioContext<Backend> {
+    pub fn create_audio_context(
+        &self,
+        options: AudioContextOptions,
+    ) -> AudioResult<AudioContext<Backend>> {
         AudioContext::new(options)
     }
 